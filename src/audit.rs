@@ -0,0 +1,139 @@
+use std::{
+    fmt::{self, Write as _},
+    net::SocketAddr,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use crate::{logfile::LogFile, time::DateTime, Code};
+
+/// A command received on a control connection and the reply it got
+#[derive(Debug, Clone)]
+pub struct AuditRecord<'a> {
+    /// When the command was received
+    pub time: SystemTime,
+
+    /// Unique to each control connection for the life of the server
+    pub session: u64,
+
+    pub remote: SocketAddr,
+
+    /// The logged in user, or during login the name given to `USER`
+    pub user: Option<&'a str>,
+
+    pub verb: &'a str,
+
+    /// The command's argument, with secrets such as passwords masked
+    pub argument: &'a str,
+
+    /// The final reply sent for the command, if any
+    pub reply: Option<Code>,
+
+    /// Time between receiving the command and sending the final reply
+    pub latency: Duration,
+}
+
+impl AuditRecord<'_> {
+    /// Formats this record as a single line JSON object
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+
+        // writing to a `String` can't fail
+        let _ = self.write_json(&mut json);
+
+        json
+    }
+
+    fn write_json(&self, json: &mut String) -> fmt::Result {
+        write!(
+            json,
+            "{{\"time\":\"{}\",\"session\":{},\"remote\":",
+            DateTime::from(self.time).rfc3339(),
+            self.session
+        )?;
+        write_string(json, &self.remote.to_string())?;
+
+        json.push_str(",\"user\":");
+        match self.user {
+            Some(user) => write_string(json, user)?,
+            None => json.push_str("null"),
+        }
+
+        json.push_str(",\"verb\":");
+        write_string(json, self.verb)?;
+
+        json.push_str(",\"argument\":");
+        write_string(json, self.argument)?;
+
+        json.push_str(",\"reply\":");
+        match self.reply {
+            Some(code) => write!(json, "{}", code)?,
+            None => json.push_str("null"),
+        }
+
+        write!(json, ",\"latency_us\":{}}}", self.latency.as_micros())
+    }
+}
+
+fn write_string(json: &mut String, s: &str) -> fmt::Result {
+    json.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => write!(json, "\\u{:04x}", c as u32)?,
+            c => json.push(c),
+        }
+    }
+
+    json.push('"');
+
+    Ok(())
+}
+
+/// Receives an [`AuditRecord`] for every command handled by the server.
+///
+/// Sinks are called on the session's thread, after the reply has been sent
+pub trait AuditSink: Send + Sync {
+    fn record(&self, record: &AuditRecord<'_>);
+}
+
+/// Lets the caller keep a handle to a sink after registering it
+impl<S: AuditSink + ?Sized> AuditSink for Arc<S> {
+    fn record(&self, record: &AuditRecord<'_>) {
+        (**self).record(record)
+    }
+}
+
+/// Appends one JSON object per line to a file
+#[derive(Debug)]
+pub struct JsonFileSink {
+    file: LogFile,
+}
+
+impl JsonFileSink {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            file: LogFile::new("audit log", path.into()),
+        }
+    }
+}
+
+impl AuditSink for JsonFileSink {
+    fn record(&self, record: &AuditRecord<'_>) {
+        self.file.append(&record.to_json());
+    }
+}
+
+/// Masks the arguments of commands which carry secrets
+pub(crate) fn sanitize<'a>(verb: &str, argument: &'a str) -> &'a str {
+    match verb {
+        "PASS" | "ACCT" if !argument.is_empty() => "****",
+        _ => argument,
+    }
+}
@@ -70,9 +70,9 @@ impl FtpConnection {
             }
         }
 
-        self.stdout.write(&self.code)?;
-        self.stdout.write(&space_or_dash)?;
-        self.stdout.write(self.message.as_bytes())?;
+        self.stdout.write_all(&self.code)?;
+        self.stdout.write_all(&space_or_dash)?;
+        self.stdout.write_all(self.message.as_bytes())?;
 
         self.stdout.flush()?;
 
@@ -83,14 +83,11 @@ impl FtpConnection {
             None => return Ok(true),
         };
 
-        Ok(match code {
-            Code::ServiceClosing => false,
-            _ => true,
-        })
+        Ok(!matches!(code, Code::ServiceClosing))
     }
 
     pub fn write_stdout(&mut self, bytes: &[u8]) -> io::Result<()> {
-        self.stdout.write(bytes)?;
+        self.stdout.write_all(bytes)?;
         self.stdout.flush()
     }
 
@@ -133,7 +130,7 @@ impl FtpConnection {
 }
 
 fn main() -> io::Result<()> {
-    let addr = match std::env::args().skip(1).next() {
+    let addr = match std::env::args().nth(1) {
         Some(addr) => addr,
         None => {
             eprintln!("Missing argument: IP");
@@ -0,0 +1,96 @@
+/// A command the server recognizes, as described by `HELP`
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct Command {
+    pub verb: &'static str,
+    pub syntax: &'static str,
+    pub implemented: bool,
+}
+
+const fn command(verb: &'static str, syntax: &'static str, implemented: bool) -> Command {
+    Command {
+        verb,
+        syntax,
+        implemented,
+    }
+}
+
+/// Every command the server recognizes, in alphabetical order
+pub(crate) const COMMANDS: &[Command] = &[
+    command("ABOR", "ABOR", true),
+    command("ACCT", "ACCT <SP> <account-information>", true),
+    command(
+        "ALLO",
+        "ALLO <SP> <decimal-integer> [<SP> R <SP> <decimal-integer>]",
+        true,
+    ),
+    command("APPE", "APPE <SP> <pathname>", false),
+    command("CDUP", "CDUP", true),
+    command("CWD", "CWD <SP> <pathname>", true),
+    command("DELE", "DELE <SP> <pathname>", true),
+    command("HELP", "HELP [<SP> <string>]", true),
+    command("LIST", "LIST [<SP> <pathname>]", false),
+    command("MKD", "MKD <SP> <pathname>", true),
+    command("MODE", "MODE <SP> <mode-code>", true),
+    command("NLST", "NLST [<SP> <pathname>]", true),
+    command("NOOP", "NOOP", true),
+    command(
+        "OPTS",
+        "OPTS <SP> <command-name> [<SP> <command-options>]",
+        true,
+    ),
+    command("PASS", "PASS <SP> <password>", true),
+    command("PASV", "PASV", true),
+    command("PORT", "PORT <SP> <host-port>", true),
+    command("PWD", "PWD", true),
+    command("QUIT", "QUIT", true),
+    command("REIN", "REIN", true),
+    command("REST", "REST <SP> <marker>", true),
+    command("RETR", "RETR <SP> <pathname>", true),
+    command("RMD", "RMD <SP> <pathname>", true),
+    command("RNFR", "RNFR <SP> <pathname>", true),
+    command("RNTO", "RNTO <SP> <pathname>", true),
+    command(
+        "SITE",
+        "SITE <SP> IDLE [<SP> <seconds>] | QUOTA | CODEPAGE [<SP> <code-page>]",
+        true,
+    ),
+    command("SMNT", "SMNT <SP> <pathname>", true),
+    command("STAT", "STAT [<SP> <pathname>]", true),
+    command("STOR", "STOR <SP> <pathname>", true),
+    command("STOU", "STOU", false),
+    command("STRU", "STRU <SP> <structure-code>", true),
+    command("SYST", "SYST", true),
+    command("TYPE", "TYPE <SP> <type-code>", true),
+    command("USER", "USER <SP> <username>", true),
+    command("XCUP", "XCUP", true),
+    command("XCWD", "XCWD <SP> <pathname>", true),
+    command("XMKD", "XMKD <SP> <pathname>", true),
+    command("XPWD", "XPWD", true),
+    command("XRMD", "XRMD <SP> <pathname>", true),
+];
+
+/// Looks up `verb`, ignoring case
+pub(crate) fn find(verb: &str) -> Option<&'static Command> {
+    COMMANDS
+        .iter()
+        .find(|command| command.verb.eq_ignore_ascii_case(verb))
+}
+
+/// The implemented verbs, eight to a line
+pub(crate) fn summary() -> String {
+    let verbs: Vec<&str> = COMMANDS
+        .iter()
+        .filter(|command| command.implemented)
+        .map(|command| command.verb)
+        .collect();
+
+    verbs
+        .chunks(8)
+        .map(|row| {
+            row.iter()
+                .map(|verb| format!(" {:<4}", verb))
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -0,0 +1,156 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    net::TcpStream,
+    sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender},
+    thread,
+    time::Duration,
+};
+
+use log::debug;
+
+use crate::telnet::{self, Filter, Output};
+
+/// Lines buffered ahead of the session before the reader stops reading
+const BACKLOG: usize = 16;
+
+/// Something the client sent on the control connection
+#[derive(Debug)]
+pub(crate) enum Input {
+    /// A command, including its line ending
+    Line(Vec<u8>),
+
+    Telnet(telnet::Event),
+}
+
+/// Reads lines from the control connection on a background thread, so that
+/// commands such as `ABOR` can be received while a transfer is running
+pub(crate) struct ControlReader {
+    inputs: Receiver<io::Result<Input>>,
+    requeued: VecDeque<Input>,
+}
+
+impl ControlReader {
+    pub fn spawn(stream: TcpStream) -> io::Result<Self> {
+        // keep the urgent Data Mark of a Synch in the stream, so that it
+        // arrives in order with the rest of the Telnet commands
+        set_oob_inline(&stream)?;
+
+        let writer = stream.try_clone()?;
+        let (sender, inputs) = mpsc::sync_channel(BACKLOG);
+
+        thread::spawn(move || {
+            if let Err(e) = read(stream, writer, &sender) {
+                let _ = sender.send(Err(e));
+            }
+        });
+
+        Ok(Self {
+            inputs,
+            requeued: VecDeque::new(),
+        })
+    }
+
+    /// Waits up to `timeout` for the next input. Returns `None` once the
+    /// client has closed the connection, and a `TimedOut` error if
+    /// `timeout` passes first
+    pub fn next(&mut self, timeout: Duration) -> io::Result<Option<Input>> {
+        if let Some(input) = self.requeued.pop_front() {
+            return Ok(Some(input));
+        }
+
+        match self.inputs.recv_timeout(timeout) {
+            Ok(input) => input.map(Some),
+            Err(RecvTimeoutError::Timeout) => Err(io::ErrorKind::TimedOut.into()),
+            Err(RecvTimeoutError::Disconnected) => Ok(None),
+        }
+    }
+
+    /// Returns `lines` to the front of the queue, to be read again in order
+    pub fn requeue(&mut self, lines: Vec<Vec<u8>>) {
+        for line in lines.into_iter().rev() {
+            self.requeued.push_front(Input::Line(line));
+        }
+    }
+}
+
+/// Passes lines and Telnet events to `sender` until the client closes the
+/// connection or the session stops listening
+fn read(
+    mut stream: TcpStream,
+    mut writer: TcpStream,
+    sender: &SyncSender<io::Result<Input>>,
+) -> io::Result<()> {
+    let mut filter = Filter::default();
+    let mut buffer = [0; 4096];
+    let mut line = Vec::new();
+
+    loop {
+        let len = match stream.read(&mut buffer) {
+            Ok(0) => {
+                // a final command without a line ending
+                if !line.is_empty() {
+                    let _ = sender.send(Ok(Input::Line(line)));
+                }
+                return Ok(());
+            }
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+
+        for &byte in &buffer[..len] {
+            let input = match filter.push(byte) {
+                Some(Output::Data(b'\n')) => {
+                    line.push(b'\n');
+                    Input::Line(std::mem::take(&mut line))
+                }
+                Some(Output::Data(byte)) => {
+                    line.push(byte);
+                    continue;
+                }
+                Some(Output::Event(event)) => Input::Telnet(event),
+                Some(Output::Reply(reply)) => {
+                    debug!("Refusing telnet option negotiation {:?}", reply);
+                    writer.write_all(&reply)?;
+                    continue;
+                }
+                None => continue,
+            };
+
+            if sender.send(Ok(input)).is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn set_oob_inline(stream: &TcpStream) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let enabled: libc::c_int = 1;
+
+    // SAFETY: the descriptor is owned by `stream`, and the option value is
+    // a `c_int` of the length given
+    let result = unsafe {
+        libc::setsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_OOBINLINE,
+            &enabled as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(unix))]
+fn set_oob_inline(_stream: &TcpStream) -> io::Result<()> {
+    Ok(())
+}
@@ -1,6 +1,48 @@
 #![allow(dead_code)]
 
-use std::fmt;
+use std::{
+    error::Error,
+    fmt,
+    io::{self, Read},
+    sync::Arc,
+};
+
+use log::warn;
+
+use crate::{ebcdic::CodePage, transfer::Progress};
+
+/// Bytes read from the underlying reader at a time by [`Converted`]
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// Introduces a control code in a record-structured stream. A literal 0xFF
+/// is sent twice
+const ESCAPE: u8 = 0xFF;
+/// End of record, as a bit of the control code after [`ESCAPE`]
+const EOR: u8 = 0x01;
+/// End of file, as a bit of the control code after [`ESCAPE`]
+const EOF: u8 = 0x02;
+
+/// Block descriptor flag for the end of a record
+const BLOCK_EOR: u8 = 0x80;
+/// Block descriptor flag for the end of the file
+const BLOCK_EOF: u8 = 0x40;
+/// Block descriptor flag for data which the sender suspects is wrong
+const BLOCK_SUSPECT: u8 = 0x20;
+/// Block descriptor flag for a block holding a restart marker
+const BLOCK_RESTART: u8 = 0x10;
+/// The most data a block's 16-bit count allows
+const MAX_BLOCK: usize = u16::MAX as usize;
+
+/// Leads a compressed mode string of up to 127 bytes sent as they are
+const LITERAL: u8 = 0x00;
+/// Leads a compressed mode string of up to 63 copies of the next byte
+const REPLICATED: u8 = 0x80;
+/// Leads a compressed mode string of up to 63 filler bytes
+const FILLER: u8 = 0xC0;
+/// The most bytes a literal string holds
+const MAX_LITERAL: usize = 0x7F;
+/// The most bytes a replicated or filler string stands for
+const MAX_RUN: usize = 0x3F;
 
 /// Data representations are handled in FTP by a user specifying a
 /// representation type.  This type may implicitly (as in ASCII or
@@ -33,7 +75,7 @@ pub enum DataType {
     ///
     /// Using the standard NVT-ASCII representation means that data
     /// must be interpreted as 8-bit bytes.
-    Ascii,
+    Ascii(FormatControl),
 
     /// This type is intended for efficient transfer between hosts
     /// which use EBCDIC for their internal character
@@ -48,7 +90,7 @@ pub enum DataType {
     /// of structure) will probably be rarely used with EBCDIC type
     /// for purposes of denoting structure, but where it is
     /// necessary the <NL> character should be used.
-    Ebcdic,
+    Ebcdic(FormatControl),
 
     /// The data are sent as contiguous bits which, for transfer,
     /// are packed into the 8-bit transfer bytes.  The receiving
@@ -97,40 +139,137 @@ pub enum DataType {
     /// may send data to one another in words by using TYPE L 36.
     /// The data would be sent in the 8-bit transmission bytes
     /// packed so that 9 transmission bytes carried two host words.
-    LocalType,
+    LocalType(LogicalByteLength),
+}
 
-    /// The types ASCII and EBCDIC also take a second (optional)
-    /// parameter; this is to indicate what kind of vertical format
-    /// control, if any, is associated with a file.  The following
-    /// data representation types are defined in FTP:
-    ///
-    /// A character file may be transferred to a host for one of
-    /// three purposes: for printing, for storage and later
-    /// retrieval, or for processing.  If a file is sent for
-    /// printing, the receiving host must know how the vertical
-    /// format control is represented.  In the second case, it must
-    /// be possible to store a file at a host and then retrieve it
-    /// later in exactly the same form.  Finally, it should be
-    /// possible to move a file from one host to another and process
-    /// the file at the second host without undue trouble.  A single
-    /// ASCII or EBCDIC format does not satisfy all these
-    /// conditions.  Therefore, these types have a second parameter
-    /// specifying one of the following three formats:
-    FormatControl(FormatControl),
+impl Default for DataType {
+    fn default() -> Self {
+        DataType::Ascii(FormatControl::NonPrint)
+    }
+}
+
+impl DataType {
+    /// Whether this type transfers bytes unchanged, as opposed to text
+    pub fn is_binary(self) -> bool {
+        matches!(self, DataType::Image | DataType::LocalType(..))
+    }
+
+    /// Whether this type transfers files exactly as they are stored
+    pub fn is_8_bit_binary(self) -> bool {
+        match self {
+            DataType::Image => true,
+            DataType::LocalType(size) => size.bits() == 8,
+            DataType::Ascii(..) | DataType::Ebcdic(..) => false,
+        }
+    }
+
+    /// The byte compressed mode abbreviates as filler: a space in text
+    /// types, or zero
+    pub(crate) fn filler(self) -> u8 {
+        match self {
+            DataType::Ascii(..) => b' ',
+            DataType::Ebcdic(..) => crate::ebcdic::SPACE,
+            DataType::Image | DataType::LocalType(..) => 0,
+        }
+    }
+
+    /// The vertical format control of a text type
+    pub fn format_control(self) -> Option<FormatControl> {
+        match self {
+            DataType::Ascii(format) | DataType::Ebcdic(format) => Some(format),
+            DataType::Image | DataType::LocalType(..) => None,
+        }
+    }
+
+    /// How the end of a line looks once converted into this type, which is
+    /// where records end. Local bytes have no lines, so a file of them is a
+    /// single record
+    fn record_end(self) -> Option<&'static [u8]> {
+        match self {
+            DataType::Ascii(..) => Some(b"\r\n"),
+            DataType::Ebcdic(..) => Some(&[crate::ebcdic::NL]),
+            DataType::Image => Some(b"\n"),
+            DataType::LocalType(..) => None,
+        }
+    }
+
+    /// Converts local files into this type for sending, in order
+    pub(crate) fn encoders(self, code_page: CodePage) -> Vec<Box<dyn Codec>> {
+        let mut encoders: Vec<Box<dyn Codec>> = Vec::new();
+
+        if let Some(FormatControl::Carriage) = self.format_control() {
+            encoders.push(Box::<CarriageEncoder>::default());
+        }
+
+        match self {
+            DataType::Ascii(..) => encoders.push(Box::new(AsciiEncoder)),
+            DataType::Ebcdic(..) => encoders.push(Box::new(code_page.encoder())),
+            DataType::LocalType(size) if size.needs_packing() => {
+                encoders.push(Box::new(LocalBytePacker::new(size)))
+            }
+            _ => {}
+        }
+
+        encoders
+    }
+
+    /// Converts data received in this type back into a local file, in
+    /// order
+    pub(crate) fn decoders(self, code_page: CodePage) -> Vec<Box<dyn Codec>> {
+        let mut decoders: Vec<Box<dyn Codec>> = Vec::new();
+
+        match self {
+            DataType::Ascii(..) => decoders.push(Box::<AsciiDecoder>::default()),
+            DataType::Ebcdic(..) => decoders.push(Box::new(code_page.decoder())),
+            DataType::LocalType(size) if size.needs_packing() => {
+                decoders.push(Box::new(LocalByteUnpacker::new(size)))
+            }
+            _ => {}
+        }
+
+        if let Some(FormatControl::Carriage) = self.format_control() {
+            decoders.push(Box::<CarriageDecoder>::default());
+        }
+
+        decoders
+    }
 }
 
 impl fmt::Display for DataType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            DataType::Ascii => f.write_str("ASCII"),
-            DataType::Ebcdic => todo!("fmt ebcdic"),
-            DataType::Image | DataType::LocalType => f.write_str("8-bit binary"),
-            DataType::FormatControl(..) => todo!("fmt formatcontrol"),
+        let (name, format) = match self {
+            DataType::Ascii(format) => ("ASCII", format),
+            DataType::Ebcdic(format) => ("EBCDIC", format),
+            DataType::Image => return f.write_str("8-bit binary"),
+            DataType::LocalType(size) if size.bits() == 8 => return f.write_str("8-bit binary"),
+            DataType::LocalType(size) => return write!(f, "local byte size {}", size.bits()),
+        };
+
+        match format {
+            FormatControl::NonPrint => f.write_str(name),
+            format => write!(f, "{} with {}", name, format),
         }
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+/// The types ASCII and EBCDIC also take a second (optional)
+/// parameter; this is to indicate what kind of vertical format
+/// control, if any, is associated with a file.  The following
+/// data representation types are defined in FTP:
+///
+/// A character file may be transferred to a host for one of
+/// three purposes: for printing, for storage and later
+/// retrieval, or for processing.  If a file is sent for
+/// printing, the receiving host must know how the vertical
+/// format control is represented.  In the second case, it must
+/// be possible to store a file at a host and then retrieve it
+/// later in exactly the same form.  Finally, it should be
+/// possible to move a file from one host to another and process
+/// the file at the second host without undue trouble.  A single
+/// ASCII or EBCDIC format does not satisfy all these
+/// conditions.  Therefore, these types have a second parameter
+/// specifying one of the following three formats:
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
 pub enum FormatControl {
     /// This is the default format to be used if the second
     /// (format) parameter is omitted.  Non-print format must be
@@ -142,6 +281,7 @@ pub enum FormatControl {
     ///
     /// Normally, this format will be used with files destined
     /// for processing or just storage.
+    #[default]
     NonPrint,
 
     /// The file contains ASCII/EBCDIC vertical format controls
@@ -149,22 +289,37 @@ pub enum FormatControl {
     /// process will interpret appropriately.  <CRLF>, in exactly
     /// this sequence, also denotes end-of-line.
     Telnet,
+
+    /// The file contains ASA (FORTRAN) vertical format control
+    /// characters.  In a line or a record formatted according to
+    /// the ASA Standard, the first character is not to be printed.
+    /// Instead, it should be used to determine the vertical
+    /// movement of the paper which should take place before the
+    /// rest of the record is printed.
+    ///
+    /// Locally, the movement is stored as the characters a printer
+    /// would be sent: see [`CarriageEncoder`].
     Carriage,
 }
 
-impl Default for DataType {
-    fn default() -> Self {
-        Self::Ascii
+impl fmt::Display for FormatControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FormatControl::NonPrint => "non-print format",
+            FormatControl::Telnet => "Telnet format controls",
+            FormatControl::Carriage => "carriage control",
+        })
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Default)]
 pub enum DataStructure {
     /// File structure is the default to be assumed if the STRUcture
     /// command has not been used.
     ///
     /// In file-structure there is no internal structure and the
     /// file is considered to be a continuous sequence of data
+    #[default]
     Files,
 
     // Record structures must be accepted for "text" files (i.e.,
@@ -184,9 +339,23 @@ pub enum DataStructure {
     Page,
 }
 
-impl Default for DataStructure {
-    fn default() -> Self {
-        DataStructure::Files
+impl DataStructure {
+    /// Divides data already converted into `data_type` up into this
+    /// structure for sending
+    pub(crate) fn encoder(self, data_type: DataType) -> Option<Box<dyn Codec>> {
+        match self {
+            DataStructure::Files | DataStructure::Page => None,
+            DataStructure::Record => Some(Box::new(RecordEncoder::new(data_type.record_end()))),
+        }
+    }
+
+    /// Joins data received in this structure back together, ready to be
+    /// converted from `data_type`
+    pub(crate) fn decoder(self, data_type: DataType) -> Option<Box<dyn Codec>> {
+        match self {
+            DataStructure::Files | DataStructure::Page => None,
+            DataStructure::Record => Some(Box::new(RecordDecoder::new(data_type.record_end()))),
+        }
     }
 }
 
@@ -200,6 +369,7 @@ impl fmt::Display for DataStructure {
     }
 }
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 #[repr(u8)]
 pub enum PageType {
     /// This is used to indicate the end of a paged
@@ -222,19 +392,118 @@ pub enum PageType {
     AccessControlled = 3,
 }
 
-/// Number of bits long a byte is (for now we assume every byte is 8 bits)
+impl PageType {
+    pub fn from_u8(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(PageType::Last),
+            1 => Some(PageType::Simple),
+            2 => Some(PageType::Descriptor),
+            3 => Some(PageType::AccessControlled),
+            _ => None,
+        }
+    }
+
+    /// The header length this type of page must have
+    pub fn header_length(self) -> u8 {
+        match self {
+            PageType::AccessControlled => 5,
+            _ => 4,
+        }
+    }
+}
+
+/// Number of bits long a logical byte is, for `TYPE L`, from 8 to 64.
+///
+/// Locally, each logical byte is stored big-endian in the smallest of 1, 2,
+/// 4 or 8 bytes which holds it, in the low bits. On the data connection,
+/// logical bytes are packed together most significant bit first, and the
+/// last transfer byte is padded with zeros.
+///
+/// Smaller logical bytes aren't supported: the padding could then hold a
+/// whole logical byte, and there would be no telling it from a zero one
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct LogicalByteLength(u8);
 
-#[derive(Debug, Copy, Clone)]
+impl LogicalByteLength {
+    pub fn new(bits: u8) -> Option<Self> {
+        if (8..=64).contains(&bits) {
+            Some(Self(bits))
+        } else {
+            None
+        }
+    }
+
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// Bytes taken by each logical byte in a local file
+    fn local_width(self) -> usize {
+        (self.0 as usize).div_ceil(8).next_power_of_two()
+    }
+
+    /// Whether the local and transfer forms differ. Logical bytes which are
+    /// a power of two bytes long are already packed
+    fn needs_packing(self) -> bool {
+        self.local_width() * 8 != self.0 as usize
+    }
+
+    fn mask(bits: u32) -> u128 {
+        (1u128 << bits) - 1
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
 pub enum TransferMode {
+    #[default]
     Stream,
     Block,
     Compressed,
 }
 
-impl Default for TransferMode {
-    fn default() -> Self {
-        TransferMode::Stream
+impl TransferMode {
+    /// Frames data for sending in this mode. `records` is whether the data
+    /// holds record structure escapes. Every `marker_interval` bytes a
+    /// restart marker is sent, and a transfer restarted at a marker skips
+    /// the data before it
+    pub(crate) fn encoder(
+        self,
+        records: bool,
+        filler: u8,
+        marker_interval: Option<u64>,
+        restart: u64,
+    ) -> Option<Box<dyn Codec>> {
+        let compression = match self {
+            TransferMode::Stream => return None,
+            TransferMode::Block => None,
+            TransferMode::Compressed => Some(filler),
+        };
+
+        Some(Box::new(BlockEncoder::new(
+            records,
+            compression,
+            marker_interval,
+            restart,
+        )))
+    }
+
+    /// Removes this mode's framing from data received, reporting restart
+    /// markers to `progress`. `restart` is where in the file the transfer
+    /// starts
+    pub(crate) fn decoder(
+        self,
+        records: bool,
+        filler: u8,
+        restart: u64,
+        progress: Arc<Progress>,
+    ) -> Option<Box<dyn Codec>> {
+        let deframed = Deframed::new(records, restart, progress);
+
+        match self {
+            TransferMode::Stream => None,
+            TransferMode::Block => Some(Box::new(BlockDecoder::new(deframed))),
+            TransferMode::Compressed => Some(Box::new(CompressedDecoder::new(filler, deframed))),
+        }
     }
 }
 
@@ -247,3 +516,1135 @@ impl fmt::Display for TransferMode {
         })
     }
 }
+
+/// A streaming conversion between the local form of a file and its form on
+/// the data connection. Input may be split at any point, so codecs carry
+/// whatever state they need from one call to the next
+pub(crate) trait Codec: Send {
+    /// Converts the next part of the stream, appending the result to
+    /// `output`
+    fn convert(&mut self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()>;
+
+    /// Appends anything held back, once the stream has ended
+    fn finish(&mut self, _output: &mut Vec<u8>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Local text to NVT-ASCII: `\n` becomes `CR LF`, and a bare `CR` becomes
+/// `CR NUL` so that it survives the trip back
+#[derive(Debug, Default)]
+pub(crate) struct AsciiEncoder;
+
+impl Codec for AsciiEncoder {
+    fn convert(&mut self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+        for &byte in input {
+            match byte {
+                b'\n' => output.extend_from_slice(b"\r\n"),
+                b'\r' => output.extend_from_slice(b"\r\0"),
+                _ => output.push(byte),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// NVT-ASCII to local text: `CR LF` becomes `\n` and `CR NUL` becomes a bare
+/// `CR`. A `CR` followed by anything else is kept as it is
+#[derive(Debug, Default)]
+pub(crate) struct AsciiDecoder {
+    /// Whether the last byte seen was a `CR`, which may have been split
+    /// from the byte that says what it means
+    carriage_return: bool,
+}
+
+impl Codec for AsciiDecoder {
+    fn convert(&mut self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+        for &byte in input {
+            if self.carriage_return {
+                self.carriage_return = false;
+
+                match byte {
+                    b'\n' => {
+                        output.push(b'\n');
+                        continue;
+                    }
+                    b'\0' => {
+                        output.push(b'\r');
+                        continue;
+                    }
+                    _ => output.push(b'\r'),
+                }
+            }
+
+            if byte == b'\r' {
+                self.carriage_return = true;
+            } else {
+                output.push(byte);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self, output: &mut Vec<u8>) -> io::Result<()> {
+        if self.carriage_return {
+            self.carriage_return = false;
+            output.push(b'\r');
+        }
+
+        Ok(())
+    }
+}
+
+/// Returned from [`Converted`] when a codec is given data which isn't valid
+/// in its TYPE, STRUcture or MODE, or which ends part way through
+#[derive(Debug)]
+pub(crate) struct Unconvertible(io::Error);
+
+impl fmt::Display for Unconvertible {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Error for Unconvertible {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+impl Unconvertible {
+    /// Whether `e` was caused by data which couldn't be converted
+    pub fn caused(e: &io::Error) -> bool {
+        e.get_ref()
+            .is_some_and(|inner| inner.downcast_ref::<Unconvertible>().is_some())
+    }
+
+    fn wrap(e: io::Error) -> io::Error {
+        io::Error::other(Unconvertible(e))
+    }
+}
+
+/// Reads `inner` through a chain of codecs, applied in order
+pub(crate) struct Converted<R> {
+    inner: R,
+    codecs: Vec<Box<dyn Codec>>,
+    chunk: Vec<u8>,
+    converted: Vec<u8>,
+    position: usize,
+    finished: bool,
+}
+
+impl<R> Converted<R> {
+    pub fn new(inner: R, codecs: Vec<Box<dyn Codec>>) -> Self {
+        Self {
+            inner,
+            codecs,
+            chunk: vec![0; CHUNK_SIZE],
+            converted: Vec::new(),
+            position: 0,
+            finished: false,
+        }
+    }
+}
+
+impl<R: Read> Converted<R> {
+    /// Reads and converts the next chunk of `inner`, which may convert to
+    /// nothing
+    fn fill(&mut self) -> io::Result<()> {
+        let len = self.inner.read(&mut self.chunk)?;
+
+        let mut data = self.chunk[..len].to_vec();
+
+        for codec in &mut self.codecs {
+            let mut output = Vec::with_capacity(data.len());
+
+            codec
+                .convert(&data, &mut output)
+                .map_err(Unconvertible::wrap)?;
+            if len == 0 {
+                codec.finish(&mut output).map_err(Unconvertible::wrap)?;
+            }
+
+            data = output;
+        }
+
+        self.converted = data;
+        self.position = 0;
+        self.finished = len == 0;
+
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Converted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.codecs.is_empty() {
+            return self.inner.read(buf);
+        }
+
+        while self.position == self.converted.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            self.fill()?;
+        }
+
+        let len = buf.len().min(self.converted.len() - self.position);
+        buf[..len].copy_from_slice(&self.converted[self.position..self.position + len]);
+        self.position += len;
+
+        Ok(len)
+    }
+}
+
+/// Whether `byte` moves the paper, rather than being printed
+fn is_motion(byte: u8) -> bool {
+    matches!(byte, b'\n' | b'\x0c' | b'\r')
+}
+
+/// The ASA control character for the start of a local run of paper motion,
+/// and how many bytes of it that character accounts for. The first line of
+/// a file has no line before it to advance from
+fn asa_control(run: &[u8], first: bool) -> (u8, usize) {
+    let newlines = run.iter().take_while(|&&byte| byte == b'\n').count();
+
+    match (first, newlines, run.first()) {
+        (false, 3.., _) => (b'-', 3),
+        (false, 2, _) => (b'0', 2),
+        (false, 1, _) => (b' ', 1),
+        (true, 2.., _) => (b'-', 2),
+        (true, 1, _) => (b'0', 1),
+        (_, 0, Some(b'\x0c')) => (b'1', 1),
+        (_, 0, Some(b'\r')) => (b'+', 1),
+        (_, _, _) => (b' ', 0),
+    }
+}
+
+/// The local paper motion for an ASA control character
+fn asa_motion(control: u8, first: bool) -> &'static [u8] {
+    match (first, control) {
+        (false, b'0') | (true, b'-') => b"\n\n",
+        (false, b'-') => b"\n\n\n",
+        (true, b'0') => b"\n",
+        (_, b'1') => b"\x0c",
+        (_, b'+') => b"\r",
+        (false, _) => b"\n",
+        (true, _) => b"",
+    }
+}
+
+/// Local print files to ASA carriage control. Locally, lines are separated
+/// by the motion a printer would be sent: `\n` to advance a line, `\f` for
+/// a new page and `\r` to overprint. Each line is sent as a control
+/// character (` `, `0`, `-`, `1` or `+`) for the motion before it, followed
+/// by its text and `\n`, which the type's own codec then converts.
+///
+/// Runs of motion which no one control character describes are sent as
+/// blank lines. The last `\n` of the file ends its last line, so a file
+/// without one gains one on the way back
+#[derive(Debug, Default)]
+pub(crate) struct CarriageEncoder {
+    /// Motion which hasn't been assigned to a line yet, since more may
+    /// follow
+    motion: Vec<u8>,
+    /// Whether a line has been started, so the next must end it first
+    started: bool,
+    /// Whether there has been any input at all
+    seen: bool,
+}
+
+impl CarriageEncoder {
+    fn flush_motion(&mut self, output: &mut Vec<u8>) {
+        let mut run = &self.motion[..];
+
+        loop {
+            let (control, len) = asa_control(run, !self.started);
+
+            if self.started {
+                output.push(b'\n');
+            }
+            output.push(control);
+            self.started = true;
+            run = &run[len..];
+
+            if run.is_empty() {
+                break;
+            }
+        }
+
+        self.motion.clear();
+    }
+}
+
+impl Codec for CarriageEncoder {
+    fn convert(&mut self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+        for &byte in input {
+            self.seen = true;
+
+            if is_motion(byte) {
+                self.motion.push(byte);
+                continue;
+            }
+
+            if !self.motion.is_empty() || !self.started {
+                self.flush_motion(output);
+            }
+            output.push(byte);
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self, output: &mut Vec<u8>) -> io::Result<()> {
+        if !self.seen {
+            return Ok(());
+        }
+
+        if self.motion.last() == Some(&b'\n') {
+            self.motion.pop();
+        }
+        if !self.motion.is_empty() || !self.started {
+            self.flush_motion(output);
+        }
+        output.push(b'\n');
+
+        Ok(())
+    }
+}
+
+/// ASA carriage control to local print files; the reverse of
+/// [`CarriageEncoder`]. Unknown control characters are treated as ` `, as
+/// printers do
+#[derive(Debug)]
+pub(crate) struct CarriageDecoder {
+    /// Whether the next byte is a line's control character
+    line_start: bool,
+    /// Whether no line has been seen yet
+    first: bool,
+}
+
+impl Default for CarriageDecoder {
+    fn default() -> Self {
+        Self {
+            line_start: true,
+            first: true,
+        }
+    }
+}
+
+impl Codec for CarriageDecoder {
+    fn convert(&mut self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+        for &byte in input {
+            if self.line_start {
+                self.line_start = false;
+
+                // an empty line has no control character at all
+                let control = if byte == b'\n' { b' ' } else { byte };
+                output.extend_from_slice(asa_motion(control, self.first));
+                self.first = false;
+
+                if byte != b'\n' {
+                    continue;
+                }
+            }
+
+            if byte == b'\n' {
+                self.line_start = true;
+            } else {
+                output.push(byte);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self, output: &mut Vec<u8>) -> io::Result<()> {
+        if !self.first {
+            output.push(b'\n');
+        }
+
+        Ok(())
+    }
+}
+
+/// Local files of widened logical bytes to packed `TYPE L` data
+pub(crate) struct LocalBytePacker {
+    size: LogicalByteLength,
+    /// A logical byte split across reads
+    partial: Vec<u8>,
+    /// Bits not yet written, in the low `pending_bits` bits
+    pending: u128,
+    pending_bits: u32,
+}
+
+impl LocalBytePacker {
+    fn new(size: LogicalByteLength) -> Self {
+        Self {
+            size,
+            partial: Vec::with_capacity(8),
+            pending: 0,
+            pending_bits: 0,
+        }
+    }
+
+    fn pack(&mut self, output: &mut Vec<u8>) -> io::Result<()> {
+        let mut value = [0; 8];
+        value[8 - self.partial.len()..].copy_from_slice(&self.partial);
+        let value = u64::from_be_bytes(value) as u128;
+        self.partial.clear();
+
+        let bits = self.size.bits() as u32;
+
+        if value >> bits != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("local byte {:#x} is wider than {} bits", value, bits),
+            ));
+        }
+
+        self.pending = (self.pending << bits) | value;
+        self.pending_bits += bits;
+
+        while self.pending_bits >= 8 {
+            self.pending_bits -= 8;
+            output.push((self.pending >> self.pending_bits) as u8);
+        }
+        self.pending &= LogicalByteLength::mask(self.pending_bits);
+
+        Ok(())
+    }
+}
+
+impl Codec for LocalBytePacker {
+    fn convert(&mut self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+        let width = self.size.local_width();
+
+        for &byte in input {
+            self.partial.push(byte);
+
+            if self.partial.len() == width {
+                self.pack(output)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self, output: &mut Vec<u8>) -> io::Result<()> {
+        if !self.partial.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "file is not a whole number of {}-byte local bytes",
+                    self.size.local_width()
+                ),
+            ));
+        }
+
+        if self.pending_bits > 0 {
+            output.push((self.pending << (8 - self.pending_bits)) as u8);
+            self.pending = 0;
+            self.pending_bits = 0;
+        }
+
+        Ok(())
+    }
+}
+
+/// Packed `TYPE L` data to local files of widened logical bytes
+pub(crate) struct LocalByteUnpacker {
+    size: LogicalByteLength,
+    pending: u128,
+    pending_bits: u32,
+}
+
+impl LocalByteUnpacker {
+    fn new(size: LogicalByteLength) -> Self {
+        Self {
+            size,
+            pending: 0,
+            pending_bits: 0,
+        }
+    }
+}
+
+impl Codec for LocalByteUnpacker {
+    fn convert(&mut self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+        let bits = self.size.bits() as u32;
+        let width = self.size.local_width();
+
+        for &byte in input {
+            self.pending = (self.pending << 8) | byte as u128;
+            self.pending_bits += 8;
+
+            while self.pending_bits >= bits {
+                self.pending_bits -= bits;
+
+                let value = (self.pending >> self.pending_bits) & LogicalByteLength::mask(bits);
+                output.extend_from_slice(&(value as u64).to_be_bytes()[8 - width..]);
+            }
+            self.pending &= LogicalByteLength::mask(self.pending_bits);
+        }
+
+        Ok(())
+    }
+
+    // whatever is left over is padding
+}
+
+/// Lines of converted data to records, marked with the escape sequences of
+/// stream mode: `0xFF 0x01` ends a record, `0xFF 0x02` ends the file and a
+/// literal 0xFF is doubled. Any other transfer mode reframes these.
+///
+/// Each line, ending with `record_end`, is a record. A last line without
+/// one is still a record, so it gains a line ending on the way back
+pub(crate) struct RecordEncoder {
+    record_end: Option<&'static [u8]>,
+    /// How much of `record_end` the latest bytes matched
+    matched: usize,
+    /// Whether a record has been started and not yet ended
+    open: bool,
+}
+
+impl RecordEncoder {
+    fn new(record_end: Option<&'static [u8]>) -> Self {
+        Self {
+            record_end,
+            matched: 0,
+            open: false,
+        }
+    }
+
+    fn data(&mut self, byte: u8, output: &mut Vec<u8>) {
+        if byte == ESCAPE {
+            output.push(ESCAPE);
+        }
+        output.push(byte);
+        self.open = true;
+    }
+
+    /// Sends the part of `record_end` which turned out not to be one
+    fn unmatch(&mut self, output: &mut Vec<u8>) {
+        if let Some(record_end) = self.record_end {
+            for &byte in &record_end[..self.matched] {
+                self.data(byte, output);
+            }
+        }
+        self.matched = 0;
+    }
+}
+
+impl Codec for RecordEncoder {
+    fn convert(&mut self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+        let record_end = match self.record_end {
+            Some(record_end) => record_end,
+            None => {
+                for &byte in input {
+                    self.data(byte, output);
+                }
+                return Ok(());
+            }
+        };
+
+        for &byte in input {
+            if byte != record_end[self.matched] {
+                self.unmatch(output);
+            }
+
+            if byte == record_end[self.matched] {
+                self.matched += 1;
+
+                if self.matched == record_end.len() {
+                    self.matched = 0;
+                    self.open = false;
+                    output.extend_from_slice(&[ESCAPE, EOR]);
+                }
+            } else {
+                self.data(byte, output);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self, output: &mut Vec<u8>) -> io::Result<()> {
+        self.unmatch(output);
+
+        let control = if self.open { EOR | EOF } else { EOF };
+        output.extend_from_slice(&[ESCAPE, control]);
+        self.open = false;
+
+        Ok(())
+    }
+}
+
+/// Records marked with stream mode escape sequences back into lines; the
+/// reverse of [`RecordEncoder`]. Anything after the end of the file is
+/// ignored
+pub(crate) struct RecordDecoder {
+    record_end: Option<&'static [u8]>,
+    /// Whether the last byte was an unpaired [`ESCAPE`]
+    escaped: bool,
+    ended: bool,
+}
+
+impl RecordDecoder {
+    fn new(record_end: Option<&'static [u8]>) -> Self {
+        Self {
+            record_end,
+            escaped: false,
+            ended: false,
+        }
+    }
+}
+
+impl Codec for RecordDecoder {
+    fn convert(&mut self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+        for &byte in input {
+            if self.ended {
+                break;
+            }
+
+            if !self.escaped {
+                if byte == ESCAPE {
+                    self.escaped = true;
+                } else {
+                    output.push(byte);
+                }
+                continue;
+            }
+
+            self.escaped = false;
+
+            match byte {
+                ESCAPE => output.push(ESCAPE),
+                1..=3 => {
+                    if byte & EOR != 0 {
+                        output.extend_from_slice(self.record_end.unwrap_or_default());
+                    }
+                    self.ended = byte & EOF != 0;
+                }
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("unknown record control code {:#04x}", byte),
+                    ))
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self, _output: &mut Vec<u8>) -> io::Result<()> {
+        if self.escaped {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "data ended within a record control code",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Frames data into blocks, each led by a descriptor of flags and a 16-bit
+/// count. Record structure escapes become `EOR` flags, and the end of the
+/// data an `EOF` flag.
+///
+/// In compressed mode, each block is sent instead as strings of literal
+/// bytes, runs of a replicated byte and runs of the filler byte. Its
+/// descriptor follows as an escape sequence of a zero byte then the flags,
+/// if it has any.
+///
+/// Restart markers give the position in the data before framing, counting
+/// each record structure escape as the two bytes it was
+pub(crate) struct BlockEncoder {
+    records: bool,
+    /// The filler byte, in compressed mode
+    compression: Option<u8>,
+    block: Vec<u8>,
+    /// Whether the last byte was an unpaired [`ESCAPE`]
+    escaped: bool,
+    /// How far into the data this is, including what was skipped
+    position: u64,
+    /// Data before this is skipped
+    restart: u64,
+    marker_interval: Option<u64>,
+    since_marker: u64,
+    ended: bool,
+}
+
+impl BlockEncoder {
+    fn new(
+        records: bool,
+        compression: Option<u8>,
+        marker_interval: Option<u64>,
+        restart: u64,
+    ) -> Self {
+        Self {
+            records,
+            compression,
+            block: Vec::with_capacity(MAX_BLOCK),
+            escaped: false,
+            position: 0,
+            restart,
+            marker_interval,
+            since_marker: 0,
+            ended: false,
+        }
+    }
+
+    /// Moves past `len` bytes of data, returning false if they come before
+    /// the restart marker
+    fn advance(&mut self, len: u64) -> bool {
+        let skip = self.position + len <= self.restart;
+
+        self.position += len;
+        if !skip {
+            self.since_marker += len;
+        }
+
+        !skip
+    }
+
+    fn flush(&mut self, descriptor: u8, output: &mut Vec<u8>) {
+        match self.compression {
+            None => {
+                output.push(descriptor);
+                output.extend_from_slice(&(self.block.len() as u16).to_be_bytes());
+                output.extend_from_slice(&self.block);
+            }
+            Some(filler) => {
+                compress(&self.block, filler, output);
+                if descriptor != 0 {
+                    output.extend_from_slice(&[LITERAL, descriptor]);
+                }
+            }
+        }
+        self.block.clear();
+
+        if descriptor & BLOCK_EOF != 0 {
+            self.ended = true;
+        }
+    }
+
+    /// Sends a restart marker if one is due, ending the current block first
+    fn mark(&mut self, output: &mut Vec<u8>) {
+        let due = self
+            .marker_interval
+            .is_some_and(|interval| self.since_marker >= interval);
+        if self.ended || !due {
+            return;
+        }
+
+        if !self.block.is_empty() {
+            self.flush(0, output);
+        }
+
+        let marker = self.position.to_string();
+        match self.compression {
+            None => {
+                output.push(BLOCK_RESTART);
+                output.extend_from_slice(&(marker.len() as u16).to_be_bytes());
+            }
+            Some(..) => output.extend_from_slice(&[LITERAL, BLOCK_RESTART, marker.len() as u8]),
+        }
+        output.extend_from_slice(marker.as_bytes());
+
+        self.since_marker = 0;
+    }
+
+    fn data(&mut self, byte: u8, len: u64, output: &mut Vec<u8>) {
+        if !self.advance(len) {
+            return;
+        }
+
+        self.block.push(byte);
+        if self.block.len() == MAX_BLOCK {
+            self.flush(0, output);
+        }
+    }
+
+    fn control(&mut self, control: u8, output: &mut Vec<u8>) {
+        if !self.advance(2) {
+            return;
+        }
+
+        let mut descriptor = 0;
+        if control & EOR != 0 {
+            descriptor |= BLOCK_EOR;
+        }
+        if control & EOF != 0 {
+            descriptor |= BLOCK_EOF;
+        }
+        self.flush(descriptor, output);
+    }
+}
+
+impl Codec for BlockEncoder {
+    fn convert(&mut self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+        for &byte in input {
+            if self.ended {
+                break;
+            }
+
+            if !self.records {
+                self.data(byte, 1, output);
+            } else if self.escaped {
+                self.escaped = false;
+
+                match byte {
+                    ESCAPE => self.data(ESCAPE, 2, output),
+                    control => self.control(control, output),
+                }
+            } else if byte == ESCAPE {
+                self.escaped = true;
+                continue;
+            } else {
+                self.data(byte, 1, output);
+            }
+
+            self.mark(output);
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self, output: &mut Vec<u8>) -> io::Result<()> {
+        if !self.ended {
+            self.flush(BLOCK_EOF, output);
+        }
+
+        Ok(())
+    }
+}
+
+/// Appends `data` as compressed mode strings. Runs of `filler` of two or
+/// more and runs of any other byte of three or more are abbreviated, which
+/// never makes the data longer than literal strings would
+fn compress(data: &[u8], filler: u8, output: &mut Vec<u8>) {
+    // where the bytes not yet sent start
+    let mut start = 0;
+    let mut i = 0;
+
+    while i < data.len() {
+        let byte = data[i];
+        let run = data[i..]
+            .iter()
+            .take(MAX_RUN)
+            .take_while(|&&next| next == byte)
+            .count();
+
+        if (byte == filler && run >= 2) || run >= 3 {
+            literal(&data[start..i], output);
+            if byte == filler {
+                output.push(FILLER | run as u8);
+            } else {
+                output.extend_from_slice(&[REPLICATED | run as u8, byte]);
+            }
+            i += run;
+            start = i;
+        } else {
+            i += 1;
+            if i - start == MAX_LITERAL {
+                literal(&data[start..i], output);
+                start = i;
+            }
+        }
+    }
+
+    literal(&data[start..], output);
+}
+
+fn literal(data: &[u8], output: &mut Vec<u8>) {
+    if !data.is_empty() {
+        output.push(LITERAL | data.len() as u8);
+        output.extend_from_slice(data);
+    }
+}
+
+/// The data, record structure escapes and restart markers which block and
+/// compressed mode framing carry
+pub(crate) struct Deframed {
+    records: bool,
+    marker: Vec<u8>,
+    position: u64,
+    progress: Arc<Progress>,
+}
+
+impl Deframed {
+    fn new(records: bool, restart: u64, progress: Arc<Progress>) -> Self {
+        Self {
+            records,
+            marker: Vec::new(),
+            position: restart,
+            progress,
+        }
+    }
+
+    fn data(&mut self, data: &[u8], output: &mut Vec<u8>) {
+        for &byte in data {
+            if self.records && byte == ESCAPE {
+                output.push(ESCAPE);
+                self.position += 1;
+            }
+            output.push(byte);
+            self.position += 1;
+        }
+    }
+
+    /// Reports the restart marker collected so far
+    fn mark(&mut self) {
+        // markers are meant to be printable, and mustn't end the reply
+        let marker = self
+            .marker
+            .drain(..)
+            .filter(u8::is_ascii_graphic)
+            .map(char::from)
+            .collect();
+        self.progress.mark(marker, self.position);
+    }
+
+    /// Acts on the flags of a descriptor other than the restart marker
+    /// flag, returning whether the file has ended
+    fn flags(&mut self, descriptor: u8, output: &mut Vec<u8>) -> bool {
+        if descriptor & BLOCK_SUSPECT != 0 {
+            warn!("Client suspects data it sent is in error.");
+        }
+
+        let mut control = 0;
+        if descriptor & BLOCK_EOR != 0 {
+            control |= EOR;
+        }
+        if descriptor & BLOCK_EOF != 0 {
+            control |= EOF;
+        }
+        if self.records && control != 0 {
+            output.extend_from_slice(&[ESCAPE, control]);
+            self.position += 2;
+        }
+
+        descriptor & BLOCK_EOF != 0
+    }
+}
+
+fn closed_early<T>() -> io::Result<T> {
+    Err(io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "data connection closed before the end of the file",
+    ))
+}
+
+#[derive(Debug)]
+enum BlockState {
+    Header(Vec<u8>),
+    Data { descriptor: u8, remaining: usize },
+    Ended,
+}
+
+/// Blocks back into data, with `EOR` flags as record structure escapes for
+/// record-structured files; the reverse of [`BlockEncoder`] in block mode.
+/// Restart markers are reported to a transfer's [`Progress`], along with
+/// the position reached
+pub(crate) struct BlockDecoder {
+    state: BlockState,
+    deframed: Deframed,
+}
+
+impl BlockDecoder {
+    fn new(deframed: Deframed) -> Self {
+        Self {
+            state: BlockState::Header(Vec::with_capacity(3)),
+            deframed,
+        }
+    }
+
+    fn end_block(&mut self, descriptor: u8, output: &mut Vec<u8>) {
+        if descriptor & BLOCK_RESTART != 0 {
+            self.deframed.mark();
+        }
+
+        self.state = if self.deframed.flags(descriptor, output) {
+            BlockState::Ended
+        } else {
+            BlockState::Header(Vec::with_capacity(3))
+        };
+    }
+}
+
+impl Codec for BlockDecoder {
+    fn convert(&mut self, mut input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+        while !input.is_empty() {
+            match &mut self.state {
+                BlockState::Header(header) => {
+                    header.push(input[0]);
+                    input = &input[1..];
+
+                    if header.len() == 3 {
+                        let descriptor = header[0];
+                        let remaining = u16::from_be_bytes([header[1], header[2]]) as usize;
+
+                        self.state = BlockState::Data {
+                            descriptor,
+                            remaining,
+                        };
+                        if remaining == 0 {
+                            self.end_block(descriptor, output);
+                        }
+                    }
+                }
+                BlockState::Data {
+                    descriptor,
+                    remaining,
+                } => {
+                    let descriptor = *descriptor;
+                    let len = input.len().min(*remaining);
+                    *remaining -= len;
+                    let remaining = *remaining;
+
+                    if descriptor & BLOCK_RESTART != 0 {
+                        self.deframed.marker.extend_from_slice(&input[..len]);
+                    } else {
+                        self.deframed.data(&input[..len], output);
+                    }
+                    input = &input[len..];
+
+                    if remaining == 0 {
+                        self.end_block(descriptor, output);
+                    }
+                }
+                // nothing follows the end of the file
+                BlockState::Ended => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self, _output: &mut Vec<u8>) -> io::Result<()> {
+        match self.state {
+            BlockState::Ended => Ok(()),
+            _ => closed_early(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+enum CompressedState {
+    /// Expecting the byte which leads a string
+    Header,
+    /// Expecting the descriptor of an escape sequence
+    Escape,
+    Literal {
+        remaining: u8,
+    },
+    Replicated {
+        count: u8,
+    },
+    Ended,
+}
+
+/// Compressed mode strings back into data; the reverse of [`BlockEncoder`]
+/// in compressed mode. A restart marker is the literal string after an
+/// escape sequence with the restart marker flag
+pub(crate) struct CompressedDecoder {
+    state: CompressedState,
+    filler: u8,
+    /// Whether the next literal string is a restart marker
+    marker_next: bool,
+    deframed: Deframed,
+}
+
+impl CompressedDecoder {
+    fn new(filler: u8, deframed: Deframed) -> Self {
+        Self {
+            state: CompressedState::Header,
+            filler,
+            marker_next: false,
+            deframed,
+        }
+    }
+
+    fn end_literal(&mut self) {
+        if self.marker_next {
+            self.marker_next = false;
+            self.deframed.mark();
+        }
+        self.state = CompressedState::Header;
+    }
+}
+
+impl Codec for CompressedDecoder {
+    fn convert(&mut self, mut input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+        while !input.is_empty() {
+            let byte = input[0];
+
+            match self.state {
+                CompressedState::Header => {
+                    input = &input[1..];
+
+                    let count = byte & MAX_RUN as u8;
+                    self.state = match byte {
+                        LITERAL => CompressedState::Escape,
+                        0x01..=0x7F => CompressedState::Literal { remaining: byte },
+                        0x80..=0xBF => CompressedState::Replicated { count },
+                        _ => {
+                            let filler = [self.filler; MAX_RUN];
+                            self.deframed.data(&filler[..count as usize], output);
+                            CompressedState::Header
+                        }
+                    };
+                }
+                CompressedState::Escape => {
+                    input = &input[1..];
+
+                    self.marker_next = byte & BLOCK_RESTART != 0;
+                    self.state = if self.deframed.flags(byte, output) {
+                        CompressedState::Ended
+                    } else {
+                        CompressedState::Header
+                    };
+                }
+                CompressedState::Literal { remaining } => {
+                    let len = input.len().min(remaining as usize);
+
+                    if self.marker_next {
+                        self.deframed.marker.extend_from_slice(&input[..len]);
+                    } else {
+                        self.deframed.data(&input[..len], output);
+                    }
+                    input = &input[len..];
+
+                    let remaining = remaining - len as u8;
+                    if remaining == 0 {
+                        self.end_literal();
+                    } else {
+                        self.state = CompressedState::Literal { remaining };
+                    }
+                }
+                CompressedState::Replicated { count } => {
+                    input = &input[1..];
+
+                    let run = [byte; MAX_RUN];
+                    self.deframed.data(&run[..count as usize], output);
+                    self.state = CompressedState::Header;
+                }
+                // nothing follows the end of the file
+                CompressedState::Ended => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self, _output: &mut Vec<u8>) -> io::Result<()> {
+        match self.state {
+            CompressedState::Ended => Ok(()),
+            _ => closed_early(),
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use std::{net::IpAddr, path::Path, sync::Arc, time::Duration};
+
+use crate::lockout::LoginAttempt;
+
+/// The session an event or operation belongs to
+#[derive(Debug, Copy, Clone)]
+pub struct SessionInfo<'a> {
+    pub ip: IpAddr,
+
+    /// The logged in user, or during login the name given to `USER`
+    pub username: Option<&'a str>,
+}
+
+/// An operation which is about to happen, and may be vetoed
+#[derive(Debug, Copy, Clone)]
+pub enum Operation<'a> {
+    Store { path: &'a Path },
+    Retrieve { path: &'a Path },
+    Delete { path: &'a Path },
+    Rename { from: &'a Path, to: &'a Path },
+    MakeDirectory { path: &'a Path },
+    RemoveDirectory { path: &'a Path },
+}
+
+/// Something which has happened
+#[derive(Debug, Copy, Clone)]
+pub enum Event<'a> {
+    Connect,
+    Login {
+        username: &'a str,
+        success: bool,
+    },
+    UploadComplete {
+        path: &'a Path,
+        bytes: u64,
+        duration: Duration,
+    },
+    DownloadComplete {
+        path: &'a Path,
+        bytes: u64,
+        duration: Duration,
+    },
+    Delete {
+        path: &'a Path,
+    },
+    Rename {
+        from: &'a Path,
+        to: &'a Path,
+    },
+    MakeDirectory {
+        path: &'a Path,
+    },
+    RemoveDirectory {
+        path: &'a Path,
+    },
+    Disconnect,
+}
+
+/// Receives session and file events from every connection.
+///
+/// Observers are called on the session's thread, so slow work should be
+/// handed off elsewhere
+pub trait Observer: Send + Sync {
+    /// Called before `operation` is carried out. Returning `Err` vetoes it,
+    /// and the message is sent to the client
+    fn before(&self, _session: &SessionInfo<'_>, _operation: &Operation<'_>) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn after(&self, _session: &SessionInfo<'_>, _event: &Event<'_>) {}
+}
+
+/// Lets the caller keep a handle to an observer after registering it
+impl<O: Observer + ?Sized> Observer for Arc<O> {
+    fn before(&self, session: &SessionInfo<'_>, operation: &Operation<'_>) -> Result<(), String> {
+        (**self).before(session, operation)
+    }
+
+    fn after(&self, session: &SessionInfo<'_>, event: &Event<'_>) {
+        (**self).after(session, event)
+    }
+}
+
+/// Adapts a [`Config::login_hook`](crate::Config::login_hook) callback
+pub(crate) struct LoginHook<F>(pub F);
+
+impl<F> Observer for LoginHook<F>
+where
+    F: Fn(&LoginAttempt<'_>) + Send + Sync,
+{
+    fn after(&self, session: &SessionInfo<'_>, event: &Event<'_>) {
+        if let Event::Login { username, success } = *event {
+            (self.0)(&LoginAttempt {
+                ip: session.ip,
+                username,
+                success,
+            });
+        }
+    }
+}
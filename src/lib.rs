@@ -1,121 +1,1064 @@
 use std::{
     collections::BTreeMap,
-    fs,
-    io::{self, BufRead, BufReader, Read, Write},
-    net::{Ipv4Addr, Shutdown, TcpListener, TcpStream, ToSocketAddrs},
-    path::PathBuf,
+    fmt,
+    fs::{self, File},
+    io::{self, Cursor, Read, Seek, SeekFrom, Write},
+    net::{IpAddr, Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
     str::FromStr,
-    sync::Arc,
+    sync::{Arc, RwLock},
     thread,
+    time::{Duration, Instant, SystemTime},
 };
 
-pub type Users = BTreeMap<String, String>;
+pub type Users = BTreeMap<String, User>;
 
-use log::debug;
+const DEFAULT_BANNER: &str = "Server ready for new user.";
 
-use crate::data::{DataStructure, DataType, TransferMode};
-pub use crate::response::Code;
+/// Bytes sent between restart markers in block mode, unless configured
+const DEFAULT_RESTART_MARKER_INTERVAL: u64 = 1024 * 1024;
 
+/// How often a running transfer is checked for completion while the control
+/// connection is watched for `ABOR` and `STAT`
+const TRANSFER_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+use log::{debug, info, warn};
+
+pub use crate::{
+    audit::{AuditRecord, AuditSink, JsonFileSink},
+    cidr::{AccessRules, Cidr, ParseCidrError},
+    ebcdic::{CodePage, UnknownCodePage},
+    hooks::{Event, Observer, Operation, SessionInfo},
+    lockout::{Ban, LockoutPolicy, LoginAttempt},
+    quota::Quota,
+    response::Code,
+    session::Limits,
+    settings::{Settings, SettingsError},
+    throttle::RateLimits,
+    timeout::Timeouts,
+    user::{AccountRequirement, User},
+};
+use crate::{
+    control::{ControlReader, Input},
+    data::{
+        Codec, Converted, DataStructure, DataType, FormatControl, LogicalByteLength, TransferMode,
+    },
+    hooks::LoginHook,
+    lockout::Lockout,
+    message::{Cookies, Message},
+    metrics::Metrics,
+    page::{PageTypeUnknown, PageWriter, PagedFile},
+    quota::{QuotaExceeded, QuotaFile, Usage},
+    session::{Session, Sessions},
+    throttle::{Direction, Throttled, Throttles, TokenBucket},
+    transfer::{Progress, Side, TransferError, Transferred},
+    xferlog::Xferlog,
+};
+
+mod audit;
+mod cidr;
+mod commands;
+mod control;
 mod data;
+mod ebcdic;
+mod hooks;
+mod listing;
+mod lockout;
+mod logfile;
+mod message;
+mod metrics;
 pub mod mock;
+mod page;
+mod quota;
 mod response;
+mod session;
+pub mod settings;
+mod telnet;
+mod throttle;
+mod time;
+mod timeout;
+mod transfer;
+mod user;
+mod xferlog;
 
 pub struct Config {
     users: Users,
+    timeouts: Timeouts,
+    limits: Limits,
+    lockout: LockoutPolicy,
+    observers: Vec<Arc<dyn Observer>>,
+    access: AccessRules,
+    rates: RateLimits,
+    xferlog: Option<Xferlog>,
+    audit_sinks: Vec<Arc<dyn AuditSink>>,
+    passive_ports: Option<RangeInclusive<u16>>,
+    banner: Message,
+    login_message: Option<Message>,
+    directory_messages: bool,
+    refresh_sessions: bool,
+    mounts: BTreeMap<String, PathBuf>,
+    code_page: CodePage,
+    restart_marker_interval: u64,
+}
+
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // users are listed by name only, to keep passwords out of logs
+        f.debug_struct("Config")
+            .field("users", &self.users.keys().collect::<Vec<_>>())
+            .field("timeouts", &self.timeouts)
+            .field("limits", &self.limits)
+            .field("lockout", &self.lockout)
+            .field("access", &self.access)
+            .field("rates", &self.rates)
+            .field("passive_ports", &self.passive_ports)
+            .field("refresh_sessions", &self.refresh_sessions)
+            .field("mounts", &self.mounts)
+            .field("code_page", &self.code_page)
+            .field("restart_marker_interval", &self.restart_marker_interval)
+            .finish_non_exhaustive()
+    }
 }
 
 impl Config {
     pub fn new(users: Users) -> Self {
-        Self { users }
+        Self {
+            users,
+            timeouts: Timeouts::default(),
+            limits: Limits::default(),
+            lockout: LockoutPolicy::default(),
+            observers: Vec::new(),
+            access: AccessRules::default(),
+            rates: RateLimits::default(),
+            xferlog: None,
+            audit_sinks: Vec::new(),
+            passive_ports: None,
+            banner: Message::Text(DEFAULT_BANNER.to_owned()),
+            login_message: None,
+            directory_messages: true,
+            refresh_sessions: false,
+            mounts: BTreeMap::new(),
+            code_page: CodePage::default(),
+            restart_marker_interval: DEFAULT_RESTART_MARKER_INTERVAL,
+        }
+    }
+
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn lockout(mut self, lockout: LockoutPolicy) -> Self {
+        self.lockout = lockout;
+        self
+    }
+
+    /// Restricts the addresses which may connect at all
+    pub fn access(mut self, access: AccessRules) -> Self {
+        self.access = access;
+        self
+    }
+
+    /// Limits the bandwidth used by data transfers
+    pub fn rate_limits(mut self, rates: RateLimits) -> Self {
+        self.rates = rates;
+        self
+    }
+
+    /// Appends a wu-ftpd style `xferlog` record to `path` for every
+    /// completed or aborted file transfer
+    pub fn xferlog<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.xferlog = Some(Xferlog::new(path.into()));
+        self
+    }
+
+    /// Appends a JSON object to `path` for every command and its reply
+    pub fn audit_log<P: Into<PathBuf>>(self, path: P) -> Self {
+        self.audit_sink(JsonFileSink::new(path))
+    }
+
+    /// Registers a sink which receives a record of every command and its
+    /// reply
+    pub fn audit_sink<S: AuditSink + 'static>(mut self, sink: S) -> Self {
+        self.audit_sinks.push(Arc::new(sink));
+        self
+    }
+
+    /// Restricts the ports `PASV` listens on, for servers behind a firewall
+    pub fn passive_ports(mut self, ports: RangeInclusive<u16>) -> Self {
+        self.passive_ports = Some(ports);
+        self
+    }
+
+    /// Replaces the text of the 220 greeting. It may span several lines,
+    /// and may contain the `%` cookies described at [`Config::login_message`]
+    pub fn banner<S: Into<String>>(mut self, banner: S) -> Self {
+        self.banner = Message::Text(banner.into());
+        self
+    }
+
+    /// Reads the 220 greeting from `path` for every connection
+    pub fn banner_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.banner = Message::File(path.into());
+        self
+    }
+
+    /// Sends `message` ahead of the 230 reply to a successful login. These
+    /// cookies are substituted:
+    ///
+    /// - `%U` the user's name
+    /// - `%T` the current UTC time
+    /// - `%F` kilobytes free on the filesystem holding the current directory
+    /// - `%C` the current directory
+    /// - `%R` and `%L` the client's and server's addresses
+    /// - `%N` and `%M` the current and maximum number of sessions
+    /// - `%%` a literal `%`
+    pub fn login_message<S: Into<String>>(mut self, message: S) -> Self {
+        self.login_message = Some(Message::Text(message.into()));
+        self
+    }
+
+    /// Reads the login message from `path` for every login
+    pub fn login_message_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.login_message = Some(Message::File(path.into()));
+        self
+    }
+
+    /// Whether the contents of a directory's `.message` file are sent in the
+    /// reply to a `CWD` into it. Enabled by default
+    pub fn directory_messages(mut self, enabled: bool) -> Self {
+        self.directory_messages = enabled;
+        self
+    }
+
+    /// Whether sessions which are already open should adopt this config when
+    /// it is applied by [`ServerHandle::reload`]. Logged in users who have
+    /// been removed, or may no longer log in from their address, are
+    /// disconnected. Sessions take up the new idle timeout, code page and
+    /// per-session rate, except where changed with `SITE`
+    pub fn refresh_sessions(mut self, refresh: bool) -> Self {
+        self.refresh_sessions = refresh;
+        self
+    }
+
+    /// Adds a mount point which logged in users can switch to with
+    /// `SMNT <name>`. `path` is relative to the server root
+    pub fn mount<S: Into<String>, P: Into<PathBuf>>(mut self, name: S, path: P) -> Self {
+        self.mounts.insert(name.into(), path.into());
+        self
+    }
+
+    /// The code page `TYPE E` transfers use until a session picks another
+    /// with `SITE CODEPAGE`. Defaults to 037
+    pub fn ebcdic_code_page(mut self, code_page: CodePage) -> Self {
+        self.code_page = code_page;
+        self
+    }
+
+    /// How many bytes block and compressed mode send between restart
+    /// markers, which clients can resume a failed download from with
+    /// `REST`. Defaults to 1 MiB; 0 sends none
+    pub fn restart_marker_interval(mut self, bytes: u64) -> Self {
+        self.restart_marker_interval = bytes;
+        self
+    }
+
+    /// Registers a callback invoked on every successful and failed `PASS`
+    pub fn login_hook<F>(self, hook: F) -> Self
+    where
+        F: Fn(&LoginAttempt<'_>) + Send + Sync + 'static,
+    {
+        self.observer(LoginHook(hook))
+    }
+
+    /// Registers an observer of session and file events. Observers are
+    /// called in the order they were registered
+    pub fn observer<O: Observer + 'static>(mut self, observer: O) -> Self {
+        self.observers.push(Arc::new(observer));
+        self
+    }
+}
+
+/// The command currently being handled
+struct PendingCommand {
+    verb: String,
+    argument: String,
+    received: SystemTime,
+    started: Instant,
+    reply: Option<Code>,
+    replied: Option<Instant>,
+}
+
+/// State shared between a [`Server`] and every connection it spawns
+#[derive(Debug)]
+struct Shared {
+    /// Replaced wholesale on reload, so that each session sees a consistent
+    /// snapshot
+    config: RwLock<Arc<Config>>,
+    sessions: Arc<Sessions>,
+    lockout: Lockout,
+    throttles: Throttles,
+    metrics: Metrics,
+}
+
+impl Shared {
+    fn new(config: Arc<Config>) -> Self {
+        Self {
+            config: RwLock::new(config),
+            sessions: Arc::default(),
+            lockout: Lockout::default(),
+            throttles: Throttles::default(),
+            metrics: Metrics::default(),
+        }
+    }
+
+    /// The config new sessions should use
+    fn config(&self) -> Arc<Config> {
+        Arc::clone(&self.config.read().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    fn set_config(&self, config: Config) {
+        *self.config.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(config);
+    }
+
+    fn render_metrics(&self) -> String {
+        self.metrics.render(self.sessions.total())
     }
 }
 
 pub struct Connection {
-    reader: BufReader<TcpStream>,
+    control: ControlReader,
     writer: TcpStream,
+    root: PathBuf,
+    /// The root of the current mount point, above which `CDUP` won't go
+    mount: PathBuf,
     path: PathBuf,
     username: Option<String>,
+    logged_in: bool,
+    /// The account accepted by `ACCT`
+    account: Option<String>,
+    /// Whether the password was accepted, but the user's account is still
+    /// needed to log in
+    awaiting_account: bool,
     config: Arc<Config>,
     data_type: DataType,
+    code_page: CodePage,
     data_structure: DataStructure,
     transfer_mode: TransferMode,
+    /// The marker given by `REST`, for the next transfer to resume from
+    restart: Option<u64>,
     data_connection: Option<TcpStream>,
+    passive_listener: Option<TcpListener>,
+    connected_at: Instant,
+    idle_timeout: Duration,
+    session: Session,
+    shared: Arc<Shared>,
+    failed_logins: u32,
+    session_throttle: Option<Arc<TokenBucket>>,
+    rename_from: Option<PathBuf>,
+    command: Option<PendingCommand>,
+    /// `ABOR` received during the current transfer, recorded once answered
+    abort: Option<PendingCommand>,
+    bytes_downloaded: u64,
+    bytes_uploaded: u64,
 }
 
 impl Connection {
+    /// Serves a control connection on its own, outside of any [`Server`].
+    /// Session limits, lockouts and rate limits then only count this
+    /// connection, and `config` is never reloaded
     pub fn new(stream: TcpStream, path: PathBuf, config: Arc<Config>) -> io::Result<Self> {
+        let shared = Arc::new(Shared::new(Arc::clone(&config)));
+        let session = shared
+            .sessions
+            .open(stream.peer_addr()?, &config.limits)
+            .map_err(|e| io::Error::other(e.to_string()))?;
+
+        Self::with_session(stream, path, config, session, shared)
+    }
+
+    fn with_session(
+        stream: TcpStream,
+        path: PathBuf,
+        config: Arc<Config>,
+        session: Session,
+        shared: Arc<Shared>,
+    ) -> io::Result<Self> {
         let mut connection = Self {
-            reader: BufReader::new(stream.try_clone()?),
+            control: ControlReader::spawn(stream.try_clone()?)?,
             writer: stream,
+            root: path.clone(),
+            mount: path.clone(),
             path,
             username: None,
+            logged_in: false,
+            account: None,
+            awaiting_account: false,
+            idle_timeout: config.timeouts.idle,
+            session_throttle: session_throttle(&config),
+            code_page: config.code_page,
             config,
             data_type: DataType::default(),
             data_structure: DataStructure::default(),
             transfer_mode: TransferMode::default(),
+            restart: None,
             data_connection: None,
+            passive_listener: None,
+            connected_at: Instant::now(),
+            session,
+            shared,
+            failed_logins: 0,
+            rename_from: None,
+            bytes_downloaded: 0,
+            bytes_uploaded: 0,
+            command: None,
+            abort: None,
         };
 
         debug!("Beginning new connection.");
 
-        connection.write_response(Code::ServiceReadyForNewUser, "Server ready for new user.")?;
+        connection.notify(&Event::Connect);
+
+        connection.greet()?;
 
         Ok(connection)
     }
 
+    /// Replies with the configured banner, as when a client connects
+    fn greet(&mut self) -> io::Result<()> {
+        let banner = self.config.banner.clone();
+        let banner = self
+            .message(&banner)
+            .unwrap_or_else(|| DEFAULT_BANNER.to_owned());
+
+        self.write_response(Code::ServiceReadyForNewUser, &banner)
+    }
+
     pub fn write_response(&mut self, code: Code, message: &str) -> io::Result<()> {
         debug!(
             "Writing response: {:?}({}) {:?}",
             code, code as u16, message
         );
 
+        if let Some(command) = &mut self.command {
+            command.reply = Some(code);
+            command.replied = Some(Instant::now());
+        }
+
+        let mut reply = String::new();
+
         if message.contains('\n') {
-            write!(self.writer, "{}-", code)?;
+            reply.push_str(&format!("{}-", code));
 
             let mut lines = message.split('\n').peekable();
 
             while let Some(line) = lines.next() {
                 if lines.peek().is_some() {
                     if line.starts_with(|c: char| c.is_ascii_digit()) {
-                        self.writer.write(b"  ")?;
+                        reply.push_str("  ");
                     }
-                    write!(self.writer, "{}\r\n", line)?;
+                    reply.push_str(&format!("{}\r\n", line));
                 } else {
-                    write!(self.writer, "{} {}\r\n", code, line)?;
+                    reply.push_str(&format!("{} {}\r\n", code, line));
                 }
             }
         } else {
-            write!(self.writer, "{} {}\r\n", code, message)?;
+            reply.push_str(&format!("{} {}\r\n", code, message));
         }
 
-        Ok(())
+        self.writer.write_all(&telnet::escape(reply.as_bytes()))
     }
 
+    /// Sends `bytes` over the data connection exactly as they are, whatever
+    /// the current TYPE. Used for directory listings, which are already
+    /// NVT-ASCII
     pub fn write_to_data_connection(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let framing = self
+            .transfer_mode
+            .encoder(false, DataType::default().filler(), None, 0)
+            .into_iter()
+            .collect();
+        self.send_data(Cursor::new(bytes.to_vec()), None, framing)?;
+
+        Ok(())
+    }
+
+    /// Sends everything in `source`, which holds `size` bytes if known, over
+    /// the data connection after converting it with `codecs`. Returns `None`
+    /// if no data connection could be opened
+    fn send_data<R: Read + Send + 'static>(
+        &mut self,
+        source: R,
+        size: Option<u64>,
+        codecs: Vec<Box<dyn Codec>>,
+    ) -> io::Result<Option<Transferred>> {
         self.write_response(Code::FileStatusOk, "Connecting to data port.")?;
-        if let Some(connection) = self.data_connection.take().as_mut() {
-            connection.write_all(bytes)?;
 
-            if !bytes.ends_with(b"\r\n") {
-                connection.write_all(b"\r\n")?;
+        let connection = match self.open_data_connection()? {
+            Some(connection) => connection,
+            None => {
+                self.shared.metrics.data_connection_failure();
+                self.write_response(Code::CannotOpenDataConnection, "No data connection")?;
+                return Ok(None);
             }
+        };
 
-            connection.flush()?;
-            connection.shutdown(Shutdown::Both)?;
-        } else {
-            self.write_response(Code::CannotOpenDataConnection, "No data connection")?;
-            return Ok(());
+        // progress is counted after conversion, so the local size only
+        // helps if nothing is converted
+        let size = size.filter(|_| codecs.is_empty());
+        let source = Converted::new(source, codecs);
+
+        let data = connection.try_clone()?;
+        let sink = Throttled::new(connection, self.throttles(Direction::Download));
+
+        let progress = Arc::new(Progress::default());
+        let transferred = self.run_transfer(source, sink, Side::Sink, data, size, progress)?;
+
+        self.finish_transfer(&transferred)?;
+
+        Ok(Some(transferred))
+    }
+
+    /// Reads the data connection into `sink`, converting it back into a local
+    /// file, until the client closes it. `restart` is where in the file the
+    /// data starts. Returns `None` if no data connection could be opened
+    fn receive_data<W: Write + Send + 'static>(
+        &mut self,
+        sink: W,
+        restart: u64,
+    ) -> io::Result<Option<Transferred>> {
+        self.write_response(Code::FileStatusOk, "Connecting to data port.")?;
+
+        let connection = match self.open_data_connection()? {
+            Some(connection) => connection,
+            None => {
+                self.shared.metrics.data_connection_failure();
+                self.write_response(Code::CannotOpenDataConnection, "No data connection")?;
+                return Ok(None);
+            }
+        };
+
+        let data = connection.try_clone()?;
+        let progress = Arc::new(Progress::default());
+        let source = Converted::new(
+            Throttled::new(connection, self.throttles(Direction::Upload)),
+            self.decoders(restart, &progress),
+        );
+
+        let transferred = self.run_transfer(source, sink, Side::Source, data, None, progress)?;
+
+        self.finish_transfer(&transferred)?;
+
+        Ok(Some(transferred))
+    }
+
+    /// Converts local files into the current TYPE, STRUcture and MODE for
+    /// sending, starting from the restart marker `restart`
+    fn encoders(&self, restart: u64) -> Vec<Box<dyn Codec>> {
+        let mut encoders = self.data_type.encoders(self.code_page);
+        encoders.extend(self.data_structure.encoder(self.data_type));
+        encoders.extend(self.transfer_mode.encoder(
+            self.is_record_structure(),
+            self.data_type.filler(),
+            Some(self.config.restart_marker_interval).filter(|&interval| interval > 0),
+            restart,
+        ));
+        encoders
+    }
+
+    /// Converts data received in the current TYPE, STRUcture and MODE back
+    /// into local files. Restart markers are reported to `progress`
+    fn decoders(&self, restart: u64, progress: &Arc<Progress>) -> Vec<Box<dyn Codec>> {
+        let mut decoders: Vec<_> = self
+            .transfer_mode
+            .decoder(
+                self.is_record_structure(),
+                self.data_type.filler(),
+                restart,
+                Arc::clone(progress),
+            )
+            .into_iter()
+            .collect();
+        decoders.extend(self.data_structure.decoder(self.data_type));
+        decoders.extend(self.data_type.decoders(self.code_page));
+        decoders
+    }
+
+    fn is_record_structure(&self) -> bool {
+        matches!(self.data_structure, DataStructure::Record)
+    }
+
+    /// Copies `source` into `sink` on another thread, while answering `STAT`
+    /// and `ABOR` on this one and reporting restart markers received. Other
+    /// commands wait until the transfer ends. `data` is the data connection,
+    /// which is closed once the transfer ends or is aborted
+    fn run_transfer<R, W>(
+        &mut self,
+        mut source: R,
+        mut sink: W,
+        network: Side,
+        data: TcpStream,
+        size: Option<u64>,
+        progress: Arc<Progress>,
+    ) -> io::Result<Transferred>
+    where
+        R: Read + Send + 'static,
+        W: Write + Send + 'static,
+    {
+        let worker = {
+            let progress = Arc::clone(&progress);
+            thread::spawn(move || transfer::copy(&mut source, &mut sink, network, &progress))
+        };
+
+        let mut held = Vec::new();
+        let mut aborted = false;
+        self.abort = None;
+
+        while !worker.is_finished() {
+            self.report_marks(&progress)?;
+
+            let line = match self.control.next(TRANSFER_POLL_INTERVAL) {
+                Ok(Some(Input::Line(line))) => line,
+                Ok(Some(Input::Telnet(event))) => {
+                    debug!("Telnet {:?} during transfer.", event);
+                    continue;
+                }
+                Err(e) if is_timeout(&e) => continue,
+                // the client is gone, so there's no one left to transfer to
+                Ok(None) | Err(..) => {
+                    progress.abort();
+                    let _ = data.shutdown(Shutdown::Both);
+                    break;
+                }
+            };
+
+            match verb(&line).as_str() {
+                "ABOR" => {
+                    debug!("Aborting transfer.");
+                    let transfer = self.interject(&line);
+                    self.abort = std::mem::replace(&mut self.command, transfer);
+                    progress.abort();
+                    let _ = data.shutdown(Shutdown::Both);
+                    aborted = true;
+                    break;
+                }
+                "STAT" => {
+                    let transfer = self.interject(&line);
+                    let message = match size {
+                        Some(size) => {
+                            format!("Transferred {} of {} bytes.", progress.bytes(), size)
+                        }
+                        None => format!("Transferred {} bytes.", progress.bytes()),
+                    };
+                    self.write_response(Code::FileStatus, &message)?;
+                    self.finish_command();
+                    self.command = transfer;
+                }
+                _ => held.push(line),
+            }
+        }
+
+        let mut transferred = worker.join().unwrap_or_else(|_| Transferred {
+            bytes: progress.bytes(),
+            result: Err(TransferError::Local(io::Error::other("transfer failed"))),
+        });
+
+        let _ = data.shutdown(Shutdown::Both);
+
+        self.report_marks(&progress)?;
+        self.control.requeue(held);
+
+        if aborted {
+            transferred.result = Err(TransferError::Aborted);
+        }
+
+        Ok(transferred)
+    }
+
+    /// Starts recording `line`, received during a transfer, in place of the
+    /// transfer's own command, which is returned
+    fn interject(&mut self, line: &[u8]) -> Option<PendingCommand> {
+        let transfer = self.command.take();
+        let line = String::from_utf8_lossy(line);
+        let verb = verb(line.as_bytes());
+        let argument = line.trim().get(verb.len()..).unwrap_or_default().trim();
+
+        self.begin_command(&verb);
+        if let Some(pending) = &mut self.command {
+            pending.argument = audit::sanitize(&verb, argument).to_owned();
+        }
+
+        transfer
+    }
+
+    /// Replies `110 MARK` for each restart marker received, with the
+    /// position to give `REST` to resume from it
+    fn report_marks(&mut self, progress: &Progress) -> io::Result<()> {
+        for mark in progress.take_marks() {
+            self.write_response(
+                Code::RestartMarkerReply,
+                &format!("MARK {} = {}", mark.marker, mark.position),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn finish_transfer(&mut self, transferred: &Transferred) -> io::Result<()> {
+        if let Err(TransferError::Network(..)) = transferred.result {
+            self.shared.metrics.data_connection_failure();
         }
 
-        self.write_response(Code::ClosingDataConnection, "Closing connection")?;
+        match &transferred.result {
+            Ok(()) => {
+                debug!("Transferred {} bytes.", transferred.bytes);
+                self.write_response(Code::ClosingDataConnection, "Closing connection")?;
+            }
+            Err(TransferError::Network(e)) if is_timeout(e) => {
+                debug!("Data connection timed out.");
+                self.write_response(Code::ConnectionClosed, "Data connection timed out.")?;
+            }
+            Err(TransferError::Network(e)) => {
+                debug!("Data connection failed: {}", e);
+                self.write_response(
+                    Code::ConnectionClosed,
+                    "Connection closed; transfer aborted.",
+                )?;
+            }
+            Err(TransferError::Invalid(e)) => {
+                debug!("Invalid data received: {}", e);
+                self.write_response(
+                    Code::ActionAborted,
+                    &format!("Invalid data received: {}.", e),
+                )?;
+            }
+            Err(TransferError::Local(e)) if PageTypeUnknown::caused(e).is_some() => {
+                debug!("Upload used an unknown page type: {}", e);
+                self.write_response(
+                    Code::PageTypeUnknown,
+                    &format!("Page type {} unknown.", PageTypeUnknown::caused(e).unwrap()),
+                )?;
+            }
+            Err(TransferError::Local(e)) if QuotaExceeded::caused(e) => {
+                debug!("Upload exceeded quota.");
+                self.write_response(
+                    Code::ExceededStorageAllocation,
+                    "Exceeded storage allocation.",
+                )?;
+            }
+            Err(TransferError::Local(e)) => {
+                debug!("Local error during transfer: {}", e);
+                self.write_response(
+                    Code::ActionAborted,
+                    &format!("Local error in processing: {}.", e),
+                )?;
+            }
+            Err(TransferError::Aborted) => {
+                debug!("Transfer aborted after {} bytes.", transferred.bytes);
+                self.write_response(
+                    Code::ConnectionClosed,
+                    "Connection closed; transfer aborted.",
+                )?;
+
+                // the rest is the reply to ABOR, which is recorded after the
+                // transfer's own command
+                if let Some(abort) = self.abort.take() {
+                    self.finish_command();
+                    self.command = Some(abort);
+                }
+                self.write_response(Code::ClosingDataConnection, "Abort successful.")?;
+            }
+        }
 
         Ok(())
     }
 
-    fn read_arg(&mut self) -> io::Result<String> {
-        let mut buffer = String::new();
-        self.reader.read_line(&mut buffer)?;
-        Ok(buffer.trim().to_owned())
+    /// Reports a file transfer which began at `start` to the xferlog and
+    /// observers
+    fn file_transferred(
+        &mut self,
+        path: &Path,
+        direction: Direction,
+        transferred: &Transferred,
+        start: Instant,
+    ) {
+        let duration = start.elapsed();
+
+        match direction {
+            Direction::Download => self.bytes_downloaded += transferred.bytes,
+            Direction::Upload => self.bytes_uploaded += transferred.bytes,
+        }
+
+        self.shared
+            .metrics
+            .transfer(direction, transferred.bytes, duration);
+
+        if let Some(xferlog) = &self.config.xferlog {
+            xferlog.record(&xferlog::Record {
+                time: SystemTime::now(),
+                duration,
+                host: self.session.ip(),
+                bytes: transferred.bytes,
+                path,
+                binary: self.data_type.is_binary(),
+                direction,
+                username: self.username.as_deref().unwrap_or("-"),
+                complete: transferred.is_complete(),
+            });
+        }
+
+        if !transferred.is_complete() {
+            return;
+        }
+
+        let bytes = transferred.bytes;
+
+        self.notify(&match direction {
+            Direction::Download => Event::DownloadComplete {
+                path,
+                bytes,
+                duration,
+            },
+            Direction::Upload => Event::UploadComplete {
+                path,
+                bytes,
+                duration,
+            },
+        });
+    }
+
+    fn session_info(&self) -> SessionInfo<'_> {
+        SessionInfo {
+            ip: self.session.ip(),
+            username: self.username.as_deref(),
+        }
+    }
+
+    /// Adopts a reloaded config if it asks for existing sessions to be
+    /// refreshed. Returns `false` if the session was closed because its user
+    /// may no longer be logged in
+    fn refresh_config(&mut self) -> io::Result<bool> {
+        let current = self.shared.config();
+
+        if Arc::ptr_eq(&current, &self.config) || !current.refresh_sessions {
+            return Ok(true);
+        }
+
+        debug!("Adopting reloaded configuration.");
+
+        let previous = std::mem::replace(&mut self.config, current);
+
+        // settings taken from the config follow it, unless the client has
+        // changed them with SITE
+        if self.idle_timeout == previous.timeouts.idle {
+            self.idle_timeout = self.config.timeouts.idle;
+        } else {
+            self.idle_timeout = self.idle_timeout.min(self.config.timeouts.max_idle);
+        }
+
+        if self.code_page == previous.code_page {
+            self.code_page = self.config.code_page;
+        }
+
+        if self.config.rates.per_session != previous.rates.per_session {
+            self.session_throttle = session_throttle(&self.config);
+        }
+
+        if !self.logged_in {
+            return Ok(true);
+        }
+
+        let message = match self.user() {
+            None => "Your account is no longer available.",
+            Some((_, user)) if !user.access.permits(self.session.ip()) => {
+                "Login no longer permitted from your address."
+            }
+            Some(..) => return Ok(true),
+        };
+
+        info!(
+            "Closing session for {:?} after reload: {}",
+            self.username, message
+        );
+
+        self.write_response(Code::ServiceNotAvailable, message)?;
+        let _ = self.writer.shutdown(Shutdown::Both);
+
+        Ok(false)
+    }
+
+    fn begin_command(&mut self, verb: &str) {
+        self.command = Some(PendingCommand {
+            verb: verb.to_owned(),
+            argument: String::new(),
+            received: SystemTime::now(),
+            started: Instant::now(),
+            reply: None,
+            replied: None,
+        });
+    }
+
+    /// Records the command read by the last call to `read_cmd` in the
+    /// metrics and audit log
+    fn finish_command(&mut self) {
+        let command = match self.command.take() {
+            Some(command) => command,
+            None => return,
+        };
+
+        self.shared.metrics.command(&command.verb, command.reply);
+
+        if self.config.audit_sinks.is_empty() {
+            return;
+        }
+
+        let record = AuditRecord {
+            time: command.received,
+            session: self.session.id(),
+            remote: self.session.remote(),
+            user: self.username.as_deref(),
+            verb: &command.verb,
+            argument: &command.argument,
+            reply: command.reply,
+            latency: command
+                .replied
+                .unwrap_or_else(Instant::now)
+                .duration_since(command.started),
+        };
+
+        for sink in &self.config.audit_sinks {
+            sink.record(&record);
+        }
+    }
+
+    fn notify(&self, event: &Event<'_>) {
+        let session = self.session_info();
+
+        for observer in &self.config.observers {
+            observer.after(&session, event);
+        }
+    }
+
+    /// Asks every observer whether `operation` may go ahead, replying with
+    /// `code` and returning false if one vetoes it
+    fn permitted(&mut self, operation: &Operation<'_>, code: Code) -> io::Result<bool> {
+        let session = self.session_info();
+
+        let veto = self
+            .config
+            .observers
+            .iter()
+            .find_map(|observer| observer.before(&session, operation).err());
+
+        match veto {
+            Some(message) => {
+                debug!("Operation {:?} vetoed: {}", operation, message);
+                self.write_response(code, &message)?;
+                Ok(false)
+            }
+            None => Ok(true),
+        }
+    }
+
+    /// The name and account of the logged in user
+    fn user(&self) -> Option<(&str, &User)> {
+        if !self.logged_in {
+            return None;
+        }
+
+        let username = self.username.as_deref()?;
+
+        Some((username, self.config.users.get(username)?))
+    }
+
+    /// The logged in user's quota, along with the directory it applies to
+    fn quota(&self) -> Option<(Quota, PathBuf)> {
+        let (_, user) = self.user()?;
+
+        let dir = match &user.home {
+            Some(home) => self.root.join(home),
+            None => self.root.clone(),
+        };
+
+        Some((user.quota?, dir))
+    }
+
+    /// Measures the storage used under `dir`, replying with an error if that
+    /// is not possible
+    fn measure_usage(&mut self, dir: &Path) -> io::Result<Option<Usage>> {
+        match Usage::measure(dir) {
+            Ok(usage) => Ok(Some(usage)),
+            Err(e) => {
+                debug!("Unable to measure usage of {:?}: {}", dir, e);
+                self.write_response(Code::ActionAborted, "Unable to determine storage usage.")?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Every rate limit which applies to a transfer in `direction`
+    fn throttles(&self, direction: Direction) -> Vec<Arc<TokenBucket>> {
+        let global = self.shared.throttles.global(self.config.rates.global);
+
+        let user = self.user().and_then(|(username, user)| {
+            let rate = match direction {
+                Direction::Download => user.download_rate,
+                Direction::Upload => user.upload_rate,
+            };
+
+            self.shared.throttles.user(username, direction, rate)
+        });
+
+        vec![global, self.session_throttle.clone(), user]
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+
+    /// Takes the data connection established by the last `PORT` or `PASV`,
+    /// waiting for the client to connect in the passive case
+    fn open_data_connection(&mut self) -> io::Result<Option<TcpStream>> {
+        let connection = match self.passive_listener.take() {
+            Some(listener) => self.accept_passive(listener)?,
+            None => self.data_connection.take(),
+        };
+
+        if let Some(connection) = &connection {
+            connection.set_read_timeout(Some(self.config.timeouts.data))?;
+            connection.set_write_timeout(Some(self.config.timeouts.data))?;
+        }
+
+        Ok(connection)
+    }
+
+    fn accept_passive(&mut self, listener: TcpListener) -> io::Result<Option<TcpStream>> {
+        let deadline = Instant::now() + self.config.timeouts.passive_accept;
+
+        listener.set_nonblocking(true)?;
+
+        loop {
+            match listener.accept() {
+                Ok((stream, addr)) => {
+                    debug!("Accepted passive data connection from {}", addr);
+                    stream.set_nonblocking(false)?;
+                    return Ok(Some(stream));
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    if Instant::now() >= deadline {
+                        debug!("Timed out waiting for passive data connection.");
+                        return Ok(None);
+                    }
+                    thread::sleep(Duration::from_millis(10));
+                }
+                Err(e) => return Err(e),
+            }
+        }
     }
 
     fn unrecognized_command(&mut self, cmd: &str) -> io::Result<()> {
@@ -124,178 +1067,1217 @@ impl Connection {
         Ok(())
     }
 
-    fn read_cmd(&mut self) -> io::Result<bool> {
-        let mut command = vec![0; 4];
-
-        let cmd_len = self.reader.read(&mut command)?;
+    fn read_cmd(&mut self, timeout: Duration) -> io::Result<bool> {
+        let line = loop {
+            match self.control.next(timeout)? {
+                Some(Input::Line(line)) => break line,
+                Some(Input::Telnet(event)) => debug!("Telnet {:?} between commands.", event),
+                None => {
+                    debug!("Client closed the control connection.");
+                    return Ok(false);
+                }
+            }
+        };
+
+        let (command, arg) = line.split_at(line.len().min(4));
+        let cmd_len = command.len();
+
+        let command = match String::from_utf8(command.to_vec()) {
+            Ok(mut cmd) => {
+                cmd.make_ascii_uppercase();
+                cmd
+            }
+            Err(..) => {
+                self.write_response(Code::CommandNotImplemented, "Command was not valid UTF-8.")?;
+                return Ok(true);
+            }
+        };
+
+        debug!("Command: {:?}", command);
+
+        self.begin_command(command.trim());
+
+        if cmd_len < 4 || command.trim().len() < 3 {
+            self.unrecognized_command(&command)?;
+            return Ok(true);
+        }
+
+        let arg = String::from_utf8_lossy(arg).trim().to_owned();
+
+        let sanitized = audit::sanitize(command.trim(), &arg);
+
+        debug!("Arg: {:?}", sanitized);
+
+        if let Some(pending) = &mut self.command {
+            pending.argument = sanitized.to_owned();
+        }
+
+        if !self.refresh_config()? {
+            return Ok(false);
+        }
+
+        // only what `HELP` lists is dispatched, so that the two agree
+        let listed = match commands::find(command.trim()) {
+            Some(listed) if listed.implemented => listed,
+            Some(..) => {
+                self.write_response(Code::CommandNotImplemented, "Command not implemented.")?;
+                return Ok(true);
+            }
+            None => {
+                self.unrecognized_command(&command)?;
+                return Ok(true);
+            }
+        };
+
+        match listed.verb {
+            "USER" => {
+                if arg.is_empty() {
+                    self.write_response(
+                        Code::InvalidParametersOrArguments,
+                        "Username may not be empty.",
+                    )?;
+                    return Ok(true);
+                }
+
+                debug!("Found username: {:?}", arg);
+
+                // a new USER starts a new login, even while logged in
+                if self.logged_in {
+                    self.session.logout();
+                    self.logged_in = false;
+                    self.rename_from = None;
+                    self.mount = self.root.clone();
+                    self.path = self.root.clone();
+                }
+
+                if !self.config.users.contains_key(&arg) {
+                    self.write_response(Code::NotLoggedIn, "User does not exist.")?;
+                    return Ok(true);
+                }
+
+                self.username = Some(arg);
+                self.account = None;
+                self.awaiting_account = false;
+
+                self.write_response(
+                    Code::UserNameOkPasswordNeeded,
+                    "Username Ok. Password needed.",
+                )?;
+            }
+            "PASS" => return self.pass(arg),
+            "ACCT" => return self.acct(arg),
+            "CWD" | "XCWD" => self.cwd(arg)?,
+            "CDUP" | "XCUP" => self.cdup()?,
+            "SMNT" => self.smnt(arg)?,
+            "REIN" => self.rein()?,
+            "QUIT" => {
+                self.write_response(Code::ServiceClosing, "Goodbye!")?;
+                return Ok(false);
+            }
+            "PORT" => {
+                let mut vals: Vec<&str> = arg.split(',').collect();
+
+                let low = vals.pop().and_then(|low| low.parse::<u8>().ok());
+                let high = vals.pop().and_then(|high| high.parse::<u8>().ok());
+                let port = match (high, low) {
+                    (Some(high), Some(low)) => u16::from_be_bytes([high, low]),
+                    _ => {
+                        self.write_response(
+                            Code::InvalidParametersOrArguments,
+                            "Port not in valid format.",
+                        )?;
+                        return Ok(true);
+                    }
+                };
+
+                let ip = match Ipv4Addr::from_str(&vals.join(".")) {
+                    Ok(addr) => addr,
+                    Err(..) => {
+                        self.write_response(
+                            Code::InvalidParametersOrArguments,
+                            "IP not in valid format.",
+                        )?;
+                        return Ok(true);
+                    }
+                };
+
+                debug!("Opening data port on {}:{}", ip, port);
+
+                self.passive_listener = None;
+                self.data_connection = Some(TcpStream::connect((ip, port))?);
+
+                self.write_response(Code::Ok, "Changed port.")?;
+            }
+            "PASV" => self.pasv()?,
+            "TYPE" => self.type_cmd(arg)?,
+            "STRU" => self.stru(arg)?,
+            "MODE" => self.mode(arg)?,
+            "RETR" => self.retr(arg)?,
+            "STOR" => self.stor(arg)?,
+            "ALLO" => self.allo(arg)?,
+            "RNFR" => self.rnfr(arg)?,
+            "RNTO" => self.rnto(arg)?,
+            "DELE" => self.dele(arg)?,
+            "RMD" | "XRMD" => self.rmd(arg)?,
+            "MKD" | "XMKD" => self.mkd(arg)?,
+            "PWD" | "XPWD" => {
+                let path: String = self.path.to_string_lossy().into();
+                self.write_response(Code::Ok, &path)?
+            }
+            "NLST" => {
+                let path = self.path.join(arg);
+                let dirs = fs::read_dir(path)?
+                    .map(|entry| {
+                        Ok(entry?
+                            .file_name()
+                            .to_str()
+                            .unwrap_or("Invalid UTF-8.")
+                            .to_owned())
+                    })
+                    .collect::<io::Result<Vec<String>>>()?
+                    .iter()
+                    .map(|name| format!("{}\r\n", name))
+                    .collect::<String>();
+
+                self.write_to_data_connection(dirs.as_bytes())?;
+            }
+            "SITE" => self.site(arg)?,
+            "SYST" => self.write_response(Code::SystemTypeName, "UNIX Type: L8")?,
+            "STAT" => self.stat(arg)?,
+            "HELP" => self.help(arg)?,
+            "NOOP" => self.write_response(Code::Ok, "NOOP")?,
+            "OPTS" => self.opts(arg)?,
+            "ABOR" => self.abor()?,
+            "REST" => self.rest(arg)?,
+            verb => {
+                warn!("{} is listed as implemented, but isn't handled.", verb);
+                self.unrecognized_command(verb)?
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Returns false if the connection should be closed
+    fn pass(&mut self, password: String) -> io::Result<bool> {
+        let username = match self.username.clone() {
+            Some(username) => username,
+            None => {
+                self.write_response(Code::BadSequenceOfCommands, "Expected `USER`.")?;
+                return Ok(true);
+            }
+        };
+
+        let ip = self.session.ip();
+
+        let (password_ok, permitted) = match self.config.users.get(&username) {
+            Some(user) => (user.password == password, user.access.permits(ip)),
+            None => (false, false),
+        };
+
+        let success = password_ok && permitted;
+
+        self.shared.metrics.login(success);
+
+        self.notify(&Event::Login {
+            username: &username,
+            success,
+        });
+
+        if password_ok && !permitted {
+            info!(
+                "Refusing login for {:?} from {}: address not permitted",
+                username, ip
+            );
+            self.write_response(Code::NotLoggedIn, "Login not permitted from your address.")?;
+            return Ok(true);
+        }
+
+        if !success {
+            let policy = self.config.lockout;
+            let mut penalty = self.shared.lockout.record_failure(ip, &username, &policy);
+            penalty.delay = penalty.delay.min(self.idle_timeout);
+
+            self.failed_logins += 1;
+
+            debug!(
+                "Failed login for {:?} from {}, delaying {:?}",
+                username, ip, penalty.delay
+            );
+
+            thread::sleep(penalty.delay);
+
+            self.write_response(Code::NotLoggedIn, "Incorrect password.")?;
+
+            if penalty.banned {
+                debug!("Banning {} for {:?}", ip, policy.ban_duration);
+                self.write_response(
+                    Code::ServiceNotAvailable,
+                    "Too many failed logins, your address is temporarily banned.",
+                )?;
+                return Ok(false);
+            }
+
+            if self.failed_logins >= policy.max_attempts {
+                self.write_response(Code::ServiceNotAvailable, "Too many failed logins.")?;
+                return Ok(false);
+            }
+
+            return Ok(true);
+        }
+
+        self.shared.lockout.record_success(ip, &username);
+
+        if self.needs_account(AccountRequirement::Login) {
+            self.awaiting_account = true;
+            self.write_response(Code::NeedAccountForLogin, "Need account for login.")?;
+            return Ok(true);
+        }
+
+        self.complete_login(username)
+    }
+
+    /// Returns false if the connection should be closed
+    fn acct(&mut self, account: String) -> io::Result<bool> {
+        let expected = match self
+            .username
+            .as_ref()
+            .and_then(|name| self.config.users.get(name))
+        {
+            Some(user) => user.account.as_ref().map(|(expected, _)| expected.clone()),
+            None => {
+                self.write_response(Code::BadSequenceOfCommands, "Expected `USER`.")?;
+                return Ok(true);
+            }
+        };
+
+        if !self.logged_in && !self.awaiting_account {
+            self.write_response(Code::BadSequenceOfCommands, "Expected `PASS`.")?;
+            return Ok(true);
+        }
+
+        match expected {
+            None => self.write_response(
+                Code::CommandNotImplementedSuperfluousAtThisSite,
+                "Account not needed.",
+            )?,
+            Some(expected) if expected == account => {
+                self.account = Some(account);
+
+                if self.awaiting_account {
+                    self.awaiting_account = false;
+                    let username = self.username.clone().expect("checked above");
+                    return self.complete_login(username);
+                }
+
+                self.write_response(Code::UserLoggedIn, "Account accepted.")?;
+            }
+            Some(..) => {
+                debug!("Incorrect account for {:?}", self.username);
+                self.awaiting_account = false;
+                self.write_response(Code::NotLoggedIn, "Incorrect account.")?;
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Whether the user has yet to send the account they need for
+    /// `requirement`
+    fn needs_account(&self, requirement: AccountRequirement) -> bool {
+        let user = self
+            .username
+            .as_ref()
+            .and_then(|name| self.config.users.get(name));
+
+        match user.and_then(|user| user.account.as_ref()) {
+            Some((_, required)) => *required == requirement && self.account.is_none(),
+            None => false,
+        }
+    }
+
+    /// Logs `username` in once their credentials have been accepted.
+    /// Returns false if the connection should be closed
+    fn complete_login(&mut self, username: String) -> io::Result<bool> {
+        let home = match self
+            .config
+            .users
+            .get(&username)
+            .and_then(|user| user.home.as_ref())
+        {
+            Some(home) => self.root.join(home),
+            None => self.path.clone(),
+        };
+
+        if !home.is_dir() {
+            info!(
+                "Refusing login for {:?}: home {:?} is not a directory",
+                username, home
+            );
+            self.write_response(Code::NotLoggedIn, "Home directory unavailable.")?;
+            return Ok(true);
+        }
+
+        if let Err(e) = self.session.login(&username, &self.config.limits) {
+            debug!("Rejecting login for {:?}: {}", username, e);
+            self.write_response(Code::ServiceNotAvailable, &e.to_string())?;
+            return Ok(false);
+        }
+
+        self.logged_in = true;
+        self.path = home;
+
+        let reply = match self.config.login_message.clone() {
+            Some(message) => match self.message(&message) {
+                Some(message) => format!("{}\nLogged in.", message),
+                None => "Logged in.".to_owned(),
+            },
+            None => "Logged in.".to_owned(),
+        };
+        self.write_response(Code::UserLoggedIn, &reply)?;
+
+        Ok(true)
+    }
+
+    fn retr(&mut self, arg: String) -> io::Result<()> {
+        let path = self.path.join(arg);
+
+        let restart = self.restart.take();
+
+        if !self.permitted(&Operation::Retrieve { path: &path }, Code::FileUnavailable)? {
+            return Ok(());
+        }
+
+        if !self.structure_permits_type()? {
+            return Ok(());
+        }
+
+        if !path.is_file() {
+            self.write_response(
+                Code::FileUnavailable,
+                &format!("Error opening {:?}: Not a file.", path),
+            )?;
+            return Ok(());
+        }
+
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                self.write_response(
+                    Code::FileUnavailable,
+                    &format!("Error opening {:?}: {}.", path, e),
+                )?;
+                return Ok(());
+            }
+        };
+
+        let start = Instant::now();
+
+        let encoders = self.encoders(restart.unwrap_or(0));
+
+        let transferred = match self.data_structure {
+            DataStructure::Page => {
+                let pages = match PagedFile::new(file) {
+                    Ok(pages) => pages,
+                    Err(e) => {
+                        self.write_response(
+                            Code::FileUnavailable,
+                            &format!("Error opening {:?}: {}.", path, e),
+                        )?;
+                        return Ok(());
+                    }
+                };
+                self.send_data(pages, None, encoders)?
+            }
+            _ => {
+                let size = file.metadata().ok().map(|metadata| metadata.len());
+                self.send_data(file, size, encoders)?
+            }
+        };
+
+        if let Some(transferred) = transferred {
+            self.file_transferred(&path, Direction::Download, &transferred, start);
+        }
+
+        Ok(())
+    }
+
+    /// Pages hold files exactly as they are stored, so they can't be sent
+    /// in any other TYPE. Replies and returns false if they would be
+    fn structure_permits_type(&mut self) -> io::Result<bool> {
+        if let DataStructure::Page = self.data_structure {
+            if !self.data_type.is_8_bit_binary() {
+                self.write_response(
+                    Code::CommandNotImplementedForThatParameter,
+                    &format!("Page structure requires TYPE I, not {}.", self.data_type),
+                )?;
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn stor(&mut self, arg: String) -> io::Result<()> {
+        let path = self.path.join(arg);
+
+        let restart = self.restart.take();
+
+        if !self.permitted(&Operation::Store { path: &path }, Code::FileNameNotAllowed)? {
+            return Ok(());
+        }
+
+        if !self.structure_permits_type()? {
+            return Ok(());
+        }
+
+        // markers are positions in the data received, which are only
+        // positions in the file if nothing is converted
+        let converted = !self.data_type.is_8_bit_binary()
+            || !matches!(self.data_structure, DataStructure::Files);
+        if restart.is_some() && converted {
+            self.write_response(
+                Code::CommandNotImplementedForThatParameter,
+                "Uploads can only be restarted in TYPE I with file structure.",
+            )?;
+            return Ok(());
+        }
+
+        if self.needs_account(AccountRequirement::Store) {
+            self.write_response(
+                Code::NeedAccountForStoringFiles,
+                "Need account for storing files.",
+            )?;
+            return Ok(());
+        }
+
+        let allowance = match self.quota() {
+            Some((quota, dir)) => {
+                let usage = match self.measure_usage(&dir)? {
+                    Some(usage) => usage,
+                    None => return Ok(()),
+                };
+                // only a file under `dir` has counted towards its usage
+                let existing = fs::metadata(&path)
+                    .ok()
+                    .filter(|meta| meta.is_file() && is_within(&path, &dir));
+
+                if existing.is_none() && !quota.allows_new_file(usage) {
+                    self.write_response(
+                        Code::ExceededStorageAllocation,
+                        "File count quota exceeded.",
+                    )?;
+                    return Ok(());
+                }
+
+                // the file being replaced no longer counts against the quota
+                let usage = Usage {
+                    bytes: usage
+                        .bytes
+                        .saturating_sub(existing.map_or(0, |meta| meta.len())),
+                    ..usage
+                };
+
+                match quota.remaining_bytes(usage) {
+                    Some(0) => {
+                        self.write_response(
+                            Code::ExceededStorageAllocation,
+                            "Storage quota exceeded.",
+                        )?;
+                        return Ok(());
+                    }
+                    remaining => remaining,
+                }
+            }
+            None => None,
+        };
+
+        // what is kept before the restart marker is already stored
+        let file = match restart {
+            Some(marker) => reopen(&path, marker).map(|file| {
+                let allowance = allowance.map(|allowance| allowance.saturating_sub(marker));
+                QuotaFile::resume(file, path.clone(), marker, allowance)
+            }),
+            None => QuotaFile::create(path.clone(), allowance),
+        };
+        let file = match file {
+            Ok(file) => file,
+            Err(e) => {
+                self.write_response(
+                    Code::FileUnavailable,
+                    &format!("Error creating {:?}: {}.", path, e),
+                )?;
+                return Ok(());
+            }
+        };
+
+        let start = Instant::now();
+        let restart = restart.unwrap_or(0);
+
+        let transferred = match self.data_structure {
+            DataStructure::Page => self.receive_data(PageWriter::new(file), restart)?,
+            _ => self.receive_data(file, restart)?,
+        };
+
+        if let Some(transferred) = transferred {
+            self.file_transferred(&path, Direction::Upload, &transferred, start);
+        }
+
+        Ok(())
+    }
+
+    fn rest(&mut self, arg: String) -> io::Result<()> {
+        if let TransferMode::Stream = self.transfer_mode {
+            self.write_response(
+                Code::CommandNotImplementedForThatParameter,
+                "Restart markers are only sent in block and compressed mode.",
+            )?;
+            return Ok(());
+        }
+
+        let marker = match arg.parse::<u64>() {
+            Ok(marker) => marker,
+            Err(..) => {
+                self.write_response(
+                    Code::InvalidParametersOrArguments,
+                    &format!("Unknown restart marker {:?}.", arg),
+                )?;
+                return Ok(());
+            }
+        };
+
+        self.restart = Some(marker);
+
+        self.write_response(
+            Code::RequestPendingMoreInformation,
+            &format!("Restarting at {}. Send RETR or STOR to resume.", marker),
+        )?;
+
+        Ok(())
+    }
+
+    fn allo(&mut self, arg: String) -> io::Result<()> {
+        let size = match arg.split_whitespace().next().map(str::parse::<u64>) {
+            Some(Ok(size)) => size,
+            _ => {
+                self.write_response(
+                    Code::InvalidParametersOrArguments,
+                    "ALLO requires a size in bytes.",
+                )?;
+                return Ok(());
+            }
+        };
+
+        let (quota, dir) = match self.quota() {
+            Some(quota) => quota,
+            None => {
+                self.write_response(
+                    Code::CommandNotImplementedSuperfluousAtThisSite,
+                    "No storage allocation necessary.",
+                )?;
+                return Ok(());
+            }
+        };
+
+        let usage = match self.measure_usage(&dir)? {
+            Some(usage) => usage,
+            None => return Ok(()),
+        };
+
+        match quota.remaining_bytes(usage) {
+            Some(remaining) if size > remaining => self.write_response(
+                Code::ExceededStorageAllocation,
+                &format!("Only {} bytes of storage remain.", remaining),
+            )?,
+            _ => self.write_response(
+                Code::Ok,
+                &format!("Storage allocation of {} bytes is available.", size),
+            )?,
+        }
+
+        Ok(())
+    }
+
+    fn pasv(&mut self) -> io::Result<()> {
+        let ip = match self.writer.local_addr()?.ip() {
+            IpAddr::V4(ip) => ip,
+            IpAddr::V6(..) => {
+                self.write_response(
+                    Code::CommandNotImplementedForThatParameter,
+                    "PASV is only supported over IPv4.",
+                )?;
+                return Ok(());
+            }
+        };
+
+        let listener = match self.bind_passive(ip)? {
+            Some(listener) => listener,
+            None => {
+                self.write_response(
+                    Code::CannotOpenDataConnection,
+                    "No passive ports available.",
+                )?;
+                return Ok(());
+            }
+        };
+        let port = listener.local_addr()?.port();
+
+        debug!("Listening for passive data connection on {}:{}", ip, port);
+
+        self.data_connection = None;
+        self.passive_listener = Some(listener);
+
+        let [h1, h2, h3, h4] = ip.octets();
+
+        self.write_response(
+            Code::EnteringPassiveMode,
+            &format!(
+                "Entering Passive Mode ({},{},{},{},{},{}).",
+                h1,
+                h2,
+                h3,
+                h4,
+                port >> 8,
+                port & 0xff
+            ),
+        )?;
+
+        Ok(())
+    }
+
+    fn smnt(&mut self, name: String) -> io::Result<()> {
+        if !self.logged_in {
+            return self.write_response(Code::NotLoggedIn, "Not logged in.");
+        }
+
+        let mount = match self.config.mounts.get(&name) {
+            Some(path) => self.root.join(path),
+            None => {
+                return self.write_response(
+                    Code::FileUnavailable,
+                    &format!("No such mount point {:?}.", name),
+                )
+            }
+        };
+
+        if !mount.is_dir() {
+            info!("Mount point {:?} at {:?} is not a directory", name, mount);
+            return self.write_response(Code::FileUnavailable, "Mount point unavailable.");
+        }
+
+        debug!("Mounting {:?} at {:?}", name, mount);
+
+        self.mount = mount.clone();
+        self.path = mount;
+
+        self.write_response(
+            Code::RequestedFileActionComplete,
+            &format!("Mounted {}.", name),
+        )
+    }
+
+    /// Returns the session to the state it was in when the client connected,
+    /// keeping the control connection open. Commands are held while a
+    /// transfer runs, so any transfer has finished by now
+    fn rein(&mut self) -> io::Result<()> {
+        debug!("Reinitializing session for {:?}", self.username);
+
+        self.session.logout();
+        self.username = None;
+        self.logged_in = false;
+        self.account = None;
+        self.awaiting_account = false;
+        self.rename_from = None;
+
+        self.data_type = DataType::default();
+        self.code_page = self.config.code_page;
+        self.data_structure = DataStructure::default();
+        self.transfer_mode = TransferMode::default();
+        self.restart = None;
+        self.passive_listener = None;
+        if let Some(connection) = self.data_connection.take() {
+            let _ = connection.shutdown(Shutdown::Both);
+        }
+
+        self.mount = self.root.clone();
+        self.path = self.root.clone();
+        self.idle_timeout = self.config.timeouts.idle;
+        self.connected_at = Instant::now();
+
+        self.greet()
+    }
+
+    /// `ABOR` outside of a transfer; [`Connection::run_transfer`] handles it
+    /// during one
+    fn abor(&mut self) -> io::Result<()> {
+        self.passive_listener = None;
+
+        if let Some(connection) = self.data_connection.take() {
+            let _ = connection.shutdown(Shutdown::Both);
+        }
+
+        self.write_response(Code::ClosingDataConnection, "No transfer to abort.")
+    }
+
+    fn stat(&mut self, arg: String) -> io::Result<()> {
+        if arg.is_empty() {
+            let status = self.status();
+            return self.write_response(Code::SystemStatus, &status);
+        }
+
+        let path = self.path.join(&arg);
+
+        match listing::list(&path) {
+            Ok(lines) => {
+                let mut status = format!("Status of {}:", arg);
+                for line in lines {
+                    status.push('\n');
+                    status.push_str(&line);
+                }
+                status.push_str("\nEnd of status.");
+
+                self.write_response(Code::FileStatus, &status)
+            }
+            Err(e) => {
+                debug!("Unable to list {:?}: {}", path, e);
+                self.write_response(Code::FileUnavailable, &format!("{}: {}.", arg, e))
+            }
+        }
+    }
+
+    /// A summary of this session, for `STAT`
+    fn status(&self) -> String {
+        let mut lines = vec!["FTP server status:".to_owned()];
+
+        lines.push(format!(" Connected to {}", self.session.remote()));
+
+        match (&self.username, self.logged_in) {
+            (Some(user), true) => lines.push(format!(" Logged in as {}", user)),
+            (Some(user), false) => lines.push(format!(" Waiting for password for {}", user)),
+            (None, _) => lines.push(" Not logged in".to_owned()),
+        }
+
+        lines.push(format!(
+            " TYPE: {}; STRUcture: {}; transfer MODE: {}",
+            self.data_type, self.data_structure, self.transfer_mode
+        ));
+
+        lines.push(match (&self.passive_listener, &self.data_connection) {
+            (Some(listener), _) => match listener.local_addr() {
+                Ok(addr) => format!(" Data connection: passive, listening on {}", addr),
+                Err(..) => " Data connection: passive".to_owned(),
+            },
+            (None, Some(stream)) => match stream.peer_addr() {
+                Ok(addr) => format!(" Data connection: active, connected to {}", addr),
+                Err(..) => " Data connection: active".to_owned(),
+            },
+            (None, None) => " Data connection: none".to_owned(),
+        });
+
+        lines.push(format!(
+            " Bytes transferred: {} downloaded, {} uploaded",
+            self.bytes_downloaded, self.bytes_uploaded
+        ));
+
+        lines.push("End of status.".to_owned());
+
+        lines.join("\n")
+    }
+
+    fn help(&mut self, arg: String) -> io::Result<()> {
+        if arg.is_empty() {
+            return self.write_response(
+                Code::HelpMessage,
+                &format!(
+                    "The following commands are recognized.\n{}\nHelp OK.",
+                    commands::summary()
+                ),
+            );
+        }
+
+        match commands::find(&arg) {
+            Some(command) if command.implemented => {
+                self.write_response(Code::HelpMessage, &format!("Syntax: {}", command.syntax))
+            }
+            Some(command) => self.write_response(
+                Code::CommandNotImplemented,
+                &format!("{} is not implemented.", command.verb),
+            ),
+            None => self.write_response(
+                Code::InvalidParametersOrArguments,
+                &format!("Unknown command {}.", arg.to_ascii_uppercase()),
+            ),
+        }
+    }
+
+    fn cwd(&mut self, arg: String) -> io::Result<()> {
+        let path = self.path.join(arg);
+
+        self.change_directory(path, Code::RequestedFileActionComplete)
+    }
+
+    fn cdup(&mut self) -> io::Result<()> {
+        let mount = self
+            .mount
+            .canonicalize()
+            .unwrap_or_else(|_| self.mount.clone());
+        let current = self
+            .path
+            .canonicalize()
+            .unwrap_or_else(|_| self.path.clone());
+
+        // moves up from the mount point as the client sees it, so that the
+        // server's own paths stay hidden
+        match current.strip_prefix(&mount).ok().and_then(Path::parent) {
+            Some(parent) if parent.as_os_str().is_empty() => {
+                self.change_directory(self.mount.clone(), Code::Ok)
+            }
+            Some(parent) => self.change_directory(self.mount.join(parent), Code::Ok),
+            None => self.write_response(Code::FileUnavailable, "Already at the top directory."),
+        }
+    }
+
+    /// Moves to `path`, replying with `code` on success
+    fn change_directory(&mut self, path: PathBuf, code: Code) -> io::Result<()> {
+        if !path.is_dir() {
+            self.write_response(
+                Code::InvalidParametersOrArguments,
+                "Path is not a directory.",
+            )?;
+            return Ok(());
+        }
+
+        self.path = path;
+
+        let message = if self.config.directory_messages {
+            self.directory_message()
+        } else {
+            None
+        };
+
+        let reply = match message {
+            Some(message) => format!("{}\nChanged directory.", message),
+            None => "Changed directory.".to_owned(),
+        };
+
+        self.write_response(code, &reply)
+    }
+
+    /// The current directory's `.message` file, if it has one
+    fn directory_message(&self) -> Option<String> {
+        let path = self.path.join(".message");
+
+        match message::read(&path) {
+            Ok(text) => Some(message::expand(&text, &self.cookies())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => {
+                warn!("Unable to read {:?}: {}", path, e);
+                None
+            }
+        }
+    }
+
+    fn cookies(&self) -> Cookies<'_> {
+        Cookies {
+            user: self.username.as_deref(),
+            remote: self.session.ip(),
+            local: self
+                .writer
+                .local_addr()
+                .map(|addr| addr.ip())
+                .unwrap_or_else(|_| self.session.ip()),
+            cwd: &self.path,
+            sessions: self.shared.sessions.total(),
+            max_sessions: self.config.limits.max_sessions,
+        }
+    }
+
+    /// Loads `message` and substitutes its cookies, or logs why it couldn't
+    fn message(&self, message: &Message) -> Option<String> {
+        match message.load() {
+            Ok(text) => Some(message::expand(&text, &self.cookies())),
+            Err(e) => {
+                warn!("Unable to load message {:?}: {}", message, e);
+                None
+            }
+        }
+    }
+
+    /// Binds a passive listener within the configured port range, or on any
+    /// port if there is none. Returns `None` if every port is in use
+    fn bind_passive(&self, ip: Ipv4Addr) -> io::Result<Option<TcpListener>> {
+        let ports = match &self.config.passive_ports {
+            Some(ports) => ports,
+            None => return TcpListener::bind((ip, 0)).map(Some),
+        };
+
+        if ports.is_empty() {
+            return Ok(None);
+        }
+
+        let len = u64::from(ports.end().saturating_sub(*ports.start())) + 1;
+
+        // spread concurrent sessions across the range rather than having
+        // them all contend for its first ports
+        let offset = self.session.id() % len;
+
+        for i in 0..len {
+            let port = *ports.start() as u64 + (offset + i) % len;
+
+            match TcpListener::bind((ip, port as u16)) {
+                Ok(listener) => return Ok(Some(listener)),
+                Err(e) if e.kind() == io::ErrorKind::AddrInUse => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn site(&mut self, arg: String) -> io::Result<()> {
+        let mut args = arg.splitn(2, ' ');
+
+        let cmd = args.next().unwrap_or_default().to_ascii_uppercase();
+        let arg = args.next().unwrap_or_default().trim();
+
+        debug!("Found SITE command: {:?} {:?}", cmd, arg);
+
+        match cmd.as_str() {
+            "IDLE" => self.site_idle(arg)?,
+            "QUOTA" => self.site_quota()?,
+            "CODEPAGE" => self.site_codepage(arg)?,
+            "" => self.write_response(Code::InvalidParametersOrArguments, "Missing argument.")?,
+            _ => self.write_response(
+                Code::CommandNotImplementedForThatParameter,
+                &format!("Unknown SITE command: {}.", cmd),
+            )?,
+        }
+
+        Ok(())
+    }
+
+    fn site_codepage(&mut self, arg: &str) -> io::Result<()> {
+        if arg.is_empty() {
+            return self.write_response(
+                Code::Ok,
+                &format!("EBCDIC code page is {}.", self.code_page),
+            );
+        }
+
+        match arg.parse::<CodePage>() {
+            Ok(code_page) => {
+                self.code_page = code_page;
+                self.write_response(Code::Ok, &format!("EBCDIC code page is now {}.", code_page))
+            }
+            Err(e) => self.write_response(
+                Code::InvalidParametersOrArguments,
+                &format!(
+                    "Unknown EBCDIC code page {:?}; 037 and 1047 are supported.",
+                    e.0
+                ),
+            ),
+        }
+    }
+
+    fn site_quota(&mut self) -> io::Result<()> {
+        let (quota, dir) = match self.quota() {
+            Some(quota) => quota,
+            None => {
+                self.write_response(Code::Ok, "No quota applies.")?;
+                return Ok(());
+            }
+        };
+
+        let usage = match self.measure_usage(&dir)? {
+            Some(usage) => usage,
+            None => return Ok(()),
+        };
+
+        self.write_response(Code::Ok, &usage.report(&quota))?;
+
+        Ok(())
+    }
+
+    fn site_idle(&mut self, arg: &str) -> io::Result<()> {
+        if arg.is_empty() {
+            self.write_response(
+                Code::Ok,
+                &format!(
+                    "Current idle timeout is {} seconds; max {} seconds.",
+                    self.idle_timeout.as_secs(),
+                    self.config.timeouts.max_idle.as_secs()
+                ),
+            )?;
+            return Ok(());
+        }
+
+        let timeout = match arg.parse::<u64>() {
+            Ok(secs) => Duration::from_secs(secs),
+            Err(..) => {
+                self.write_response(
+                    Code::InvalidParametersOrArguments,
+                    "Idle timeout must be a number of seconds.",
+                )?;
+                return Ok(());
+            }
+        };
+
+        let Timeouts {
+            min_idle, max_idle, ..
+        } = self.config.timeouts;
+
+        if timeout < min_idle || timeout > max_idle {
+            self.write_response(
+                Code::InvalidParametersOrArguments,
+                &format!(
+                    "Idle timeout must be between {} and {} seconds.",
+                    min_idle.as_secs(),
+                    max_idle.as_secs()
+                ),
+            )?;
+            return Ok(());
+        }
+
+        self.idle_timeout = timeout;
+
+        self.write_response(
+            Code::Ok,
+            &format!("Idle timeout is now {} seconds.", timeout.as_secs()),
+        )?;
+
+        Ok(())
+    }
+
+    fn opts(&mut self, arg: String) -> io::Result<()> {
+        debug!("Found opts: {:?}", arg);
+
+        match arg.to_ascii_lowercase().as_str() {
+            "utf8 on" => self.write_response(Code::Ok, "Ok, UTF-8 enabled.")?,
+            _ => self.write_response(Code::CommandNotImplemented, "Unknown option.")?,
+        }
+
+        Ok(())
+    }
+
+    fn mkd(&mut self, arg: String) -> io::Result<()> {
+        let path = self.path.join(arg);
 
-        let command = match String::from_utf8(command) {
-            Ok(mut cmd) => {
-                cmd.make_ascii_uppercase();
-                cmd
-            }
-            Err(..) => {
-                self.write_response(Code::CommandNotImplemented, "Command was not valid UTF-8.")?;
-                return Ok(true);
+        if !path.exists() {
+            if !self.permitted(
+                &Operation::MakeDirectory { path: &path },
+                Code::FileNameNotAllowed,
+            )? {
+                return Ok(());
             }
-        };
 
-        debug!("Command: {:?}", command);
+            if let Err(e) = fs::create_dir(&path) {
+                self.write_response(
+                    Code::FileUnavailable,
+                    &format!("Error creating {:?}: {}.", path, e),
+                )?;
+                return Ok(());
+            }
 
-        if cmd_len < 4 || command.trim().len() < 3 {
-            self.unrecognized_command(&command)?;
-            return Ok(true);
+            self.notify(&Event::MakeDirectory { path: &path });
         }
 
-        let arg = self.read_arg()?;
-
-        debug!("Arg: {:?}", arg);
+        self.write_response(
+            Code::PathNameCreated,
+            &format!("Successfully created {:?}.", path),
+        )?;
 
-        match command.as_str() {
-            "USER" => {
-                if arg.is_empty() {
-                    self.write_response(
-                        Code::InvalidParametersOrArguments,
-                        "Username may not be empty.",
-                    )?;
-                    return Ok(true);
-                }
+        Ok(())
+    }
 
-                debug!("Found username: {:?}", arg);
+    fn dele(&mut self, arg: String) -> io::Result<()> {
+        let path = self.path.join(arg);
 
-                if !self.config.users.contains_key(&arg) {
-                    self.write_response(Code::NotLoggedIn, "User does not exist.")?;
-                    return Ok(true);
-                }
+        if !path.is_file() {
+            self.write_response(
+                Code::FileUnavailable,
+                &format!("Error deleting {:?}: Not a file.", path),
+            )?;
+            return Ok(());
+        }
 
-                self.username = Some(arg);
+        if !self.permitted(&Operation::Delete { path: &path }, Code::FileUnavailable)? {
+            return Ok(());
+        }
 
+        match fs::remove_file(&path) {
+            Ok(()) => {
+                self.notify(&Event::Delete { path: &path });
                 self.write_response(
-                    Code::UserNameOkPasswordNeeded,
-                    "Username Ok. Password needed.",
-                )?;
-            }
-            "PASS" => {
-                debug!("Found password: {:?}", arg);
-
-                if let Some(username) = &self.username {
-                    if self.config.users.get(username) == Some(&arg) {
-                        self.write_response(Code::UserLoggedIn, "Logged in.")?;
-                    } else {
-                        self.write_response(Code::NotLoggedIn, "Incorrect password.")?;
-                    }
-                } else {
-                    self.write_response(Code::BadSequenceOfCommands, "Expected `USER`.")?;
-                }
-            }
-            "ACCT" => todo!(),
-            "XCWD" | "CWD " => {
-                let path = self.path.join(arg);
-                if !path.is_dir() {
-                    self.write_response(
-                        Code::InvalidParametersOrArguments,
-                        "Path is not a directory.",
-                    )?;
-                    return Ok(true);
-                }
-                self.path = path;
-                self.write_response(Code::Ok, "Changed directory.")?
+                    Code::RequestedFileActionComplete,
+                    &format!("Successfully deleted {:?}.", path),
+                )?
             }
-            "CDUP" => todo!(),
-            "SMNT" => todo!(),
-            "QUIT" => {
-                self.write_response(Code::ServiceClosing, "Goodbye!")?;
-                return Ok(false);
-            }
-            "REIN" => todo!(),
-            "PORT" => {
-                let mut vals: Vec<&str> = arg.split(',').collect();
-
-                let port = vals.pop().unwrap().parse::<u16>().unwrap()
-                    + (vals.pop().unwrap().parse::<u16>().unwrap() << 8);
+            Err(e) => self.write_response(
+                Code::FileUnavailable,
+                &format!("Error deleting {:?}: {}.", path, e),
+            )?,
+        }
 
-                let ip = match Ipv4Addr::from_str(&vals.join(".")) {
-                    Ok(addr) => addr,
-                    Err(..) => {
-                        self.write_response(
-                            Code::InvalidParametersOrArguments,
-                            "IP not in valid format.",
-                        )?;
-                        return Ok(true);
-                    }
-                };
+        Ok(())
+    }
 
-                debug!("Opening data port on {}:{}", ip, port);
+    fn rnfr(&mut self, arg: String) -> io::Result<()> {
+        let path = self.path.join(arg);
 
-                self.data_connection = Some(TcpStream::connect((ip, port))?);
+        if !path.exists() {
+            self.write_response(
+                Code::FileUnavailable,
+                &format!("Error renaming {:?}: No such file or directory.", path),
+            )?;
+            return Ok(());
+        }
 
-                self.write_response(Code::Ok, "Changed port.")?;
-            }
-            "PASV" => todo!(),
-            "TYPE" => self.type_cmd(arg)?,
-            "STRU" => self.stru(arg)?,
-            "MODE" => self.mode(arg)?,
-            "RETR" => todo!(),
-            "STOR" => todo!(),
-            "STOU" => todo!(),
-            "APPE" => todo!(),
-            "ALLO" => todo!(),
-            "REST" => todo!(),
-            "RNFR" => todo!(),
-            "RNTO" => todo!(),
-            "ABOR" => todo!(),
-            "DELE" => todo!(),
-            "XRMD" | "RMD " | "RMD\r" => self.rmd(arg)?,
-            "XMKD" | "MKD " | "MKD\r" => {
-                let path = self.path.join(arg);
+        self.rename_from = Some(path);
 
-                if !path.exists() {
-                    fs::create_dir(&path)?;
-                }
+        self.write_response(
+            Code::RequestPendingMoreInformation,
+            "Ready for destination name.",
+        )?;
 
-                self.write_response(
-                    Code::PathNameCreated,
-                    &format!("Successfully created {:?}.", path),
-                )?;
-            }
-            "XPWD" | "PWD\r" | "PWD " => {
-                let path: String = self.path.to_string_lossy().into();
-                self.write_response(Code::Ok, &path)?
-            }
-            "LIST" => todo!(),
-            "NLST" => {
-                let path = self.path.join(arg);
-                let dirs = fs::read_dir(path)?
-                    .map(|entry| {
-                        Ok(entry?
-                            .file_name()
-                            .to_str()
-                            .unwrap_or("Invalid UTF-8.")
-                            .to_owned())
-                    })
-                    .collect::<io::Result<Vec<String>>>()?
-                    .join("\r\n");
+        Ok(())
+    }
 
-                self.write_to_data_connection(dirs.as_bytes())?;
+    fn rnto(&mut self, arg: String) -> io::Result<()> {
+        let from = match self.rename_from.take() {
+            Some(from) => from,
+            None => {
+                self.write_response(Code::BadSequenceOfCommands, "Expected `RNFR`.")?;
+                return Ok(());
             }
-            "SITE" => todo!(),
-            "SYST" => todo!(),
-            "STAT" => todo!(),
-            "HELP" => todo!(),
-            "NOOP" => self.write_response(Code::Ok, "NOOP")?,
-            "OPTS" => self.opts(arg)?,
-            cmd => self.unrecognized_command(cmd)?,
-        }
+        };
 
-        Ok(true)
-    }
+        let to = self.path.join(arg);
 
-    fn opts(&mut self, arg: String) -> io::Result<()> {
-        debug!("Found opts: {:?}", arg);
+        if !self.permitted(
+            &Operation::Rename {
+                from: &from,
+                to: &to,
+            },
+            Code::FileNameNotAllowed,
+        )? {
+            return Ok(());
+        }
 
-        match arg.to_ascii_lowercase().as_str() {
-            "utf8 on" => self.write_response(Code::Ok, "Ok, UTF-8 enabled.")?,
-            _ => self.write_response(Code::CommandNotImplemented, "Unknown option.")?,
+        match fs::rename(&from, &to) {
+            Ok(()) => {
+                self.notify(&Event::Rename {
+                    from: &from,
+                    to: &to,
+                });
+                self.write_response(
+                    Code::RequestedFileActionComplete,
+                    &format!("Renamed {:?} to {:?}.", from, to),
+                )?
+            }
+            Err(e) => self.write_response(
+                Code::FileNameNotAllowed,
+                &format!("Error renaming {:?}: {}.", from, e),
+            )?,
         }
 
         Ok(())
@@ -312,11 +2294,21 @@ impl Connection {
             return Ok(());
         }
 
+        if !self.permitted(
+            &Operation::RemoveDirectory { path: &path },
+            Code::FileUnavailable,
+        )? {
+            return Ok(());
+        }
+
         match fs::remove_dir(&path) {
-            Ok(()) => self.write_response(
-                Code::RequestedFileActionComplete,
-                &format!("Successfully deleted {:?}.", path),
-            )?,
+            Ok(()) => {
+                self.notify(&Event::RemoveDirectory { path: &path });
+                self.write_response(
+                    Code::RequestedFileActionComplete,
+                    &format!("Successfully deleted {:?}.", path),
+                )?
+            }
             Err(e) => self.write_response(
                 Code::ActionNotTaken,
                 &format!("Error deleting {:?}: {}.", path, e),
@@ -345,6 +2337,7 @@ impl Connection {
         };
 
         self.transfer_mode = transfer_mode;
+        self.restart = None;
 
         self.write_response(
             Code::Ok,
@@ -380,19 +2373,50 @@ impl Connection {
     }
 
     fn type_cmd(&mut self, arg: String) -> io::Result<()> {
-        let data_type = match arg.chars().next() {
-            Some('A') | Some('a') => DataType::Ascii,
-            Some('E') | Some('e') => DataType::Ebcdic,
+        let mut chars = arg.chars();
+
+        let data_type = match chars.next() {
+            Some(c @ ('A' | 'a' | 'E' | 'e')) => {
+                let format = match chars.as_str().trim().chars().next() {
+                    None | Some('N') | Some('n') => FormatControl::NonPrint,
+                    Some('T') | Some('t') => FormatControl::Telnet,
+                    Some('C') | Some('c') => FormatControl::Carriage,
+                    Some(format) => {
+                        self.write_response(
+                            Code::CommandNotImplementedForThatParameter,
+                            &format!("Unknown format control: {}.", format),
+                        )?;
+                        return Ok(());
+                    }
+                };
+
+                if c.eq_ignore_ascii_case(&'A') {
+                    DataType::Ascii(format)
+                } else {
+                    DataType::Ebcdic(format)
+                }
+            }
             Some('I') | Some('i') => DataType::Image,
-            Some('L') => {
-                if arg[1..].trim() != "8" {
-                    self.write_response(
-                        Code::CommandNotImplementedForThatParameter,
-                        "Only 8-bit bytes are supported.",
-                    )?;
-                    return Ok(());
+            Some('L') | Some('l') => {
+                let size = chars.as_str().trim();
+
+                match size.parse().ok().and_then(LogicalByteLength::new) {
+                    Some(size) => DataType::LocalType(size),
+                    None if size.is_empty() => {
+                        self.write_response(
+                            Code::InvalidParametersOrArguments,
+                            "Missing byte size.",
+                        )?;
+                        return Ok(());
+                    }
+                    None => {
+                        self.write_response(
+                            Code::CommandNotImplementedForThatParameter,
+                            "Byte size must be from 8 to 64.",
+                        )?;
+                        return Ok(());
+                    }
                 }
-                DataType::LocalType
             }
             Some(c) => {
                 self.write_response(
@@ -413,39 +2437,294 @@ impl Connection {
         Ok(())
     }
 
+    /// The time remaining before the login deadline, if the user has not yet
+    /// logged in
+    fn login_time_remaining(&self) -> Option<Duration> {
+        if self.logged_in {
+            return None;
+        }
+
+        let deadline = self.connected_at + self.config.timeouts.login;
+
+        Some(deadline.saturating_duration_since(Instant::now()))
+    }
+
+    fn time_out(&mut self) -> io::Result<()> {
+        let message = match self.login_time_remaining() {
+            Some(remaining) if remaining == Duration::from_secs(0) => "Login timed out.",
+            _ => "Idle timeout, closing control connection.",
+        };
+
+        debug!("{}", message);
+
+        self.write_response(Code::ServiceNotAvailable, message)?;
+        self.writer.shutdown(Shutdown::Both)?;
+
+        Ok(())
+    }
+
     pub fn command_loop(&mut self) -> io::Result<()> {
         loop {
-            if !self.read_cmd()? {
-                break;
+            let timeout = match self.login_time_remaining() {
+                Some(remaining) if remaining == Duration::from_secs(0) => {
+                    return self.time_out();
+                }
+                Some(remaining) => remaining.min(self.idle_timeout),
+                None => self.idle_timeout,
+            };
+
+            let result = self.read_cmd(timeout);
+
+            self.finish_command();
+
+            match result {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) if is_timeout(&e) => return self.time_out(),
+                Err(e) => return Err(e),
             }
         }
         Ok(())
     }
 }
 
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // also stops the control reader's thread, which holds a clone of
+        // the stream
+        let _ = self.writer.shutdown(Shutdown::Both);
+    }
+}
+
+/// The verb of a command line read from the control connection, uppercased
+fn verb(line: &[u8]) -> String {
+    let line = String::from_utf8_lossy(line);
+
+    line.split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_ascii_uppercase()
+}
+
+/// Socket timeouts surface as `WouldBlock` on unix and `TimedOut` on windows
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+/// Whether `path` is `dir` or somewhere beneath it, once links and `..` are
+/// resolved
+fn is_within(path: &Path, dir: &Path) -> bool {
+    match (path.canonicalize(), dir.canonicalize()) {
+        (Ok(path), Ok(dir)) => path.starts_with(dir),
+        _ => false,
+    }
+}
+
+/// The rate limit of a single session under `config`
+fn session_throttle(config: &Config) -> Option<Arc<TokenBucket>> {
+    config
+        .rates
+        .per_session
+        .map(|rate| Arc::new(TokenBucket::new(rate)))
+}
+
+/// Opens a partial upload to resume at `marker`, dropping anything after it
+fn reopen(path: &Path, marker: u64) -> io::Result<File> {
+    let mut file = fs::OpenOptions::new().write(true).open(path)?;
+
+    if file.metadata()?.len() < marker {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("restart marker {} is past the end of the file", marker),
+        ));
+    }
+
+    file.set_len(marker)?;
+    file.seek(SeekFrom::Start(marker))?;
+
+    Ok(file)
+}
+
+/// A cheaply cloneable view into a running [`Server`]
+#[derive(Debug, Clone)]
+pub struct ServerHandle {
+    shared: Arc<Shared>,
+}
+
+impl ServerHandle {
+    /// Applies `config` to every session which starts from now on. Sessions
+    /// which are already open adopt it at their next command if it enables
+    /// [`Config::refresh_sessions`], and otherwise keep the config they
+    /// started with
+    pub fn reload(&self, config: Config) {
+        info!("Reloading configuration");
+        self.shared.set_config(config);
+    }
+
+    /// The server's metrics, in the Prometheus text exposition format
+    pub fn metrics(&self) -> String {
+        self.shared.render_metrics()
+    }
+
+    /// Serves the server's metrics over HTTP at `GET /metrics` on a
+    /// background thread, returning the address it is listening on
+    pub fn serve_metrics<A: ToSocketAddrs>(&self, addr: A) -> io::Result<SocketAddr> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let shared = Arc::clone(&self.shared);
+
+        thread::spawn(move || metrics::serve(listener, || shared.render_metrics()));
+
+        Ok(local_addr)
+    }
+
+    /// The number of open control connections
+    pub fn sessions(&self) -> usize {
+        self.shared.sessions.total()
+    }
+
+    /// The number of open control connections from `ip`
+    pub fn sessions_from(&self, ip: IpAddr) -> usize {
+        self.shared.sessions.for_ip(ip)
+    }
+
+    /// The number of open control connections logged in as `user`
+    pub fn sessions_for(&self, user: &str) -> usize {
+        self.shared.sessions.for_user(user)
+    }
+
+    /// Every IP address which is currently banned, in ascending order
+    pub fn bans(&self) -> Vec<Ban> {
+        self.shared.lockout.bans()
+    }
+
+    /// Refuses connections from `ip` for `duration`
+    pub fn ban(&self, ip: IpAddr, duration: Duration) {
+        self.shared.lockout.ban(ip, duration)
+    }
+
+    /// Lifts the ban on `ip`, returning whether it was banned
+    pub fn unban(&self, ip: IpAddr) -> bool {
+        self.shared.lockout.unban(ip)
+    }
+
+    pub fn clear_bans(&self) {
+        self.shared.lockout.clear_bans()
+    }
+}
+
 pub struct Server {
-    listener: TcpListener,
-    config: Arc<Config>,
+    listeners: Vec<TcpListener>,
     root_path: PathBuf,
+    shared: Arc<Shared>,
 }
 
 impl Server {
+    /// Binds to `addr`, panicking if it is unavailable
     pub fn new<A: ToSocketAddrs>(addr: A, config: Config, root_path: PathBuf) -> Self {
-        Server {
-            listener: TcpListener::bind(addr).unwrap(),
-            config: Arc::new(config),
+        Self::bind(addr, config, root_path).unwrap()
+    }
+
+    pub fn bind<A: ToSocketAddrs>(addr: A, config: Config, root_path: PathBuf) -> io::Result<Self> {
+        Ok(Server {
+            listeners: vec![TcpListener::bind(addr)?],
             root_path,
+            shared: Arc::new(Shared::new(Arc::new(config))),
+        })
+    }
+
+    /// Additionally accepts connections on `addr`
+    pub fn listen<A: ToSocketAddrs>(&mut self, addr: A) -> io::Result<()> {
+        self.listeners.push(TcpListener::bind(addr)?);
+        Ok(())
+    }
+
+    /// The addresses this server is accepting connections on
+    pub fn local_addrs(&self) -> io::Result<Vec<SocketAddr>> {
+        self.listeners.iter().map(TcpListener::local_addr).collect()
+    }
+
+    pub fn handle(&self) -> ServerHandle {
+        ServerHandle {
+            shared: Arc::clone(&self.shared),
         }
     }
 
-    pub fn run(self) -> io::Result<()> {
-        for stream in self.listener.incoming() {
-            let stream = stream?;
+    /// Accepts connections on every listening address until one of them
+    /// fails
+    pub fn run(mut self) -> io::Result<()> {
+        let mut listeners = std::mem::take(&mut self.listeners);
+        let first = listeners.remove(0);
+        let server = Arc::new(self);
+
+        for listener in listeners {
+            let server = Arc::clone(&server);
+
+            thread::spawn(move || {
+                if let Err(e) = server.accept(&listener) {
+                    warn!("Stopped accepting connections on {:?}: {}", listener, e);
+                }
+            });
+        }
+
+        server.accept(&first)
+    }
+
+    fn accept(&self, listener: &TcpListener) -> io::Result<()> {
+        for stream in listener.incoming() {
+            let mut stream = stream?;
+
+            let remote = match stream.peer_addr() {
+                Ok(addr) => addr,
+                Err(e) => {
+                    debug!("Dropping connection without a peer address: {}", e);
+                    continue;
+                }
+            };
+            let ip = remote.ip();
+            let config = self.shared.config();
+
+            if !config.access.permits(ip) {
+                info!("Refusing connection from {}: address not permitted", ip);
+                let _ = write!(
+                    stream,
+                    "{} Access denied from your address.\r\n",
+                    Code::ServiceNotAvailable
+                );
+                let _ = stream.shutdown(Shutdown::Both);
+                continue;
+            }
+
+            if self.shared.lockout.is_banned(ip) {
+                debug!("Rejecting connection from banned address {}", ip);
+                let _ = write!(
+                    stream,
+                    "{} Your address is temporarily banned.\r\n",
+                    Code::ServiceNotAvailable
+                );
+                let _ = stream.shutdown(Shutdown::Both);
+                continue;
+            }
+
+            let session = match self.shared.sessions.open(remote, &config.limits) {
+                Ok(session) => session,
+                Err(e) => {
+                    debug!("Rejecting connection from {}: {}", ip, e);
+                    let _ = write!(stream, "{} {}\r\n", Code::ServiceNotAvailable, e);
+                    let _ = stream.shutdown(Shutdown::Both);
+                    continue;
+                }
+            };
 
-            let config = self.config.clone();
             let root_path = self.root_path.clone();
+            let shared = Arc::clone(&self.shared);
 
-            thread::spawn(move || Self::handle_connection(stream, config, root_path));
+            thread::spawn(move || {
+                Self::handle_connection(stream, config, root_path, session, shared)
+            });
         }
 
         Ok(())
@@ -455,11 +2734,15 @@ impl Server {
         stream: TcpStream,
         config: Arc<Config>,
         root_path: PathBuf,
+        session: Session,
+        shared: Arc<Shared>,
     ) -> io::Result<()> {
-        let mut connection = Connection::new(stream, root_path, config)?;
+        let mut connection = Connection::with_session(stream, root_path, config, session, shared)?;
 
-        connection.command_loop()?;
+        let result = connection.command_loop();
 
-        Ok(())
+        connection.notify(&Event::Disconnect);
+
+        result
     }
 }
@@ -0,0 +1,122 @@
+use std::{
+    fs::{self, Metadata},
+    io,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use crate::time::DateTime;
+
+/// Files modified longer ago than this are listed with their year rather
+/// than their time, as `ls` does
+const RECENT: Duration = Duration::from_secs(180 * 24 * 60 * 60);
+
+/// Lists `path` in the style of `ls -l`: each entry of a directory sorted by
+/// name, or a single line for anything else
+pub(crate) fn list(path: &Path) -> io::Result<Vec<String>> {
+    let metadata = path.symlink_metadata()?;
+
+    if !metadata.is_dir() {
+        let name = path
+            .file_name()
+            .unwrap_or(path.as_os_str())
+            .to_string_lossy();
+        return Ok(vec![entry(&name, path, &metadata)]);
+    }
+
+    let mut entries = fs::read_dir(path)?
+        .map(|entry| {
+            let entry = entry?;
+            let metadata = entry.path().symlink_metadata()?;
+
+            Ok((
+                entry.file_name().to_string_lossy().into_owned(),
+                entry.path(),
+                metadata,
+            ))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+    Ok(entries
+        .iter()
+        .map(|(name, path, metadata)| entry(name, path, metadata))
+        .collect())
+}
+
+fn entry(name: &str, path: &Path, metadata: &Metadata) -> String {
+    let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+    let recent = SystemTime::now()
+        .duration_since(modified)
+        .is_ok_and(|age| age < RECENT);
+
+    let mut line = format!(
+        "{}{} {:>3} ftp      ftp      {:>12} {} {}",
+        kind(metadata),
+        permissions(metadata),
+        links(metadata),
+        metadata.len(),
+        DateTime::from(modified).ls(recent),
+        name
+    );
+
+    if metadata.file_type().is_symlink() {
+        if let Ok(target) = fs::read_link(path) {
+            line.push_str(&format!(" -> {}", target.display()));
+        }
+    }
+
+    line
+}
+
+fn kind(metadata: &Metadata) -> char {
+    let file_type = metadata.file_type();
+
+    if file_type.is_dir() {
+        'd'
+    } else if file_type.is_symlink() {
+        'l'
+    } else {
+        '-'
+    }
+}
+
+#[cfg(unix)]
+fn permissions(metadata: &Metadata) -> String {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = metadata.permissions().mode();
+
+    (0..9)
+        .map(|i| {
+            if mode & (0o400 >> i) == 0 {
+                '-'
+            } else {
+                ['r', 'w', 'x'][i % 3]
+            }
+        })
+        .collect()
+}
+
+#[cfg(not(unix))]
+fn permissions(metadata: &Metadata) -> String {
+    match (metadata.is_dir(), metadata.permissions().readonly()) {
+        (true, _) => "rwxr-xr-x",
+        (false, true) => "r--r--r--",
+        (false, false) => "rw-r--r--",
+    }
+    .to_owned()
+}
+
+#[cfg(unix)]
+fn links(metadata: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+
+    metadata.nlink()
+}
+
+#[cfg(not(unix))]
+fn links(_metadata: &Metadata) -> u64 {
+    1
+}
@@ -0,0 +1,196 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    net::IpAddr,
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+/// How failed logins are throttled and punished
+#[derive(Debug, Copy, Clone)]
+pub struct LockoutPolicy {
+    /// Failed `PASS` attempts a single session may make before it is
+    /// disconnected
+    pub max_attempts: u32,
+
+    /// Delay before replying to the first failed login. Each further failure
+    /// from the same IP or against the same user doubles it
+    pub base_delay: Duration,
+
+    /// Upper bound on the delay before a failed login reply. The session
+    /// keeps its slot while it waits, and never waits longer than the idle
+    /// timeout
+    pub max_delay: Duration,
+
+    /// Failures from a single IP after which that IP is banned. `None`
+    /// disables banning
+    pub ban_after: Option<u32>,
+
+    /// How long a banned IP is refused
+    pub ban_duration: Duration,
+
+    /// How long failures are remembered after the most recent one
+    pub window: Duration,
+}
+
+impl Default for LockoutPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+            ban_after: Some(10),
+            ban_duration: Duration::from_secs(15 * 60),
+            window: Duration::from_secs(15 * 60),
+        }
+    }
+}
+
+/// A login attempt, reported to [`Config::login_hook`](crate::Config::login_hook)
+#[derive(Debug, Clone)]
+pub struct LoginAttempt<'a> {
+    pub ip: IpAddr,
+    pub username: &'a str,
+    pub success: bool,
+}
+
+/// An IP address which is currently refused
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Ban {
+    pub ip: IpAddr,
+    pub remaining: Duration,
+}
+
+impl fmt::Display for Ban {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} for {}s", self.ip, self.remaining.as_secs())
+    }
+}
+
+/// The consequences of a failed login
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct Penalty {
+    /// How long to wait before replying
+    pub delay: Duration,
+
+    /// Whether the client's IP is now banned
+    pub banned: bool,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Failures {
+    count: u32,
+    last: Instant,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    ips: HashMap<IpAddr, Failures>,
+    users: HashMap<String, Failures>,
+    bans: HashMap<IpAddr, Instant>,
+}
+
+/// Failed login counts and bans, shared by the server and its connections
+#[derive(Debug, Default)]
+pub(crate) struct Lockout {
+    state: Mutex<State>,
+}
+
+fn record<K: std::hash::Hash + Eq>(map: &mut HashMap<K, Failures>, key: K, now: Instant) -> u32 {
+    let failures = map.entry(key).or_insert(Failures {
+        count: 0,
+        last: now,
+    });
+
+    failures.count += 1;
+    failures.last = now;
+
+    failures.count
+}
+
+impl Lockout {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn is_banned(&self, ip: IpAddr) -> bool {
+        let mut state = self.state();
+
+        match state.bans.get(&ip) {
+            Some(&until) if until > Instant::now() => true,
+            Some(..) => {
+                state.bans.remove(&ip);
+                false
+            }
+            None => false,
+        }
+    }
+
+    pub fn record_failure(&self, ip: IpAddr, user: &str, policy: &LockoutPolicy) -> Penalty {
+        let now = Instant::now();
+        let mut state = self.state();
+
+        // forget failures which can no longer count, so the maps stay bounded
+        let recent = |failures: &Failures| now.duration_since(failures.last) <= policy.window;
+        state.ips.retain(|_, failures| recent(failures));
+        state.users.retain(|_, failures| recent(failures));
+
+        let ip_failures = record(&mut state.ips, ip, now);
+        let user_failures = record(&mut state.users, user.to_owned(), now);
+
+        let banned = policy.ban_after.is_some_and(|max| ip_failures >= max);
+
+        if banned {
+            state.bans.insert(ip, now + policy.ban_duration);
+            state.ips.remove(&ip);
+        }
+
+        let doublings = ip_failures.max(user_failures).saturating_sub(1).min(31);
+        let delay = policy
+            .base_delay
+            .checked_mul(1 << doublings)
+            .unwrap_or(policy.max_delay)
+            .min(policy.max_delay);
+
+        Penalty { delay, banned }
+    }
+
+    pub fn record_success(&self, ip: IpAddr, user: &str) {
+        let mut state = self.state();
+
+        state.ips.remove(&ip);
+        state.users.remove(user);
+    }
+
+    pub fn bans(&self) -> Vec<Ban> {
+        let now = Instant::now();
+        let mut state = self.state();
+
+        state.bans.retain(|_, until| *until > now);
+
+        let mut bans: Vec<Ban> = state
+            .bans
+            .iter()
+            .map(|(&ip, &until)| Ban {
+                ip,
+                remaining: until - now,
+            })
+            .collect();
+
+        bans.sort_by_key(|ban| ban.ip);
+
+        bans
+    }
+
+    pub fn ban(&self, ip: IpAddr, duration: Duration) {
+        self.state().bans.insert(ip, Instant::now() + duration);
+    }
+
+    pub fn unban(&self, ip: IpAddr) -> bool {
+        self.state().bans.remove(&ip).is_some()
+    }
+
+    pub fn clear_bans(&self) {
+        self.state().bans.clear();
+    }
+}
@@ -0,0 +1,43 @@
+use std::{fs::OpenOptions, io::Write, path::PathBuf, sync::Mutex};
+
+use log::warn;
+
+/// A log file which lines are appended to.
+///
+/// The file is reopened for every line so that it can be rotated from
+/// underneath a running server. Failures are logged rather than returned, so
+/// that a broken log never interrupts a session
+#[derive(Debug)]
+pub(crate) struct LogFile {
+    /// What the log holds, for warnings
+    name: &'static str,
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl LogFile {
+    pub fn new(name: &'static str, path: PathBuf) -> Self {
+        Self {
+            name,
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Appends `line` and a newline in a single write
+    pub fn append(&self, line: &str) {
+        let line = format!("{}\n", line);
+
+        let _lock = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        let result = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(line.as_bytes()));
+
+        if let Err(e) = result {
+            warn!("Unable to write to {} {:?}: {}", self.name, self.path, e);
+        }
+    }
+}
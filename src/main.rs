@@ -1,14 +1,218 @@
-use std::{io, path::PathBuf};
+use std::{env, io, path::PathBuf, process};
 
-use ftp::{mock::test_users, Config, Server};
+use ftp::{mock::test_users, Config, Server, ServerHandle, Settings};
 
-fn main() -> io::Result<()> {
-    env_logger::init();
+const DEFAULT_LISTEN: &str = "127.0.0.1:21";
 
-    Server::new(
-        "127.0.0.1:21",
-        Config::new(test_users()),
-        PathBuf::from("/"),
+const USAGE: &str = "\
+Usage: server [OPTIONS]
+
+Options:
+    -c, --config <FILE>         Read settings from a TOML file
+    -l, --listen <ADDR>         Accept connections on ADDR; may be repeated
+    -r, --root <DIR>            Serve DIR
+        --passive-ports <A-B>   Only listen on ports A through B for PASV
+        --banner <TEXT>         Greet clients with TEXT
+        --log-level <FILTER>    Log at FILTER, such as `info` or `ftp=debug`
+        --metrics <ADDR>        Serve Prometheus metrics on ADDR
+        --insecure-test-users   Also accept the built-in users a/a and b/b
+        --check                 Validate the configuration and exit
+    -h, --help                  Print this message
+";
+
+#[derive(Debug, Default, Clone)]
+struct Args {
+    config: Option<PathBuf>,
+    listen: Vec<String>,
+    root: Option<PathBuf>,
+    passive_ports: Option<String>,
+    banner: Option<String>,
+    log_level: Option<String>,
+    metrics: Option<String>,
+    test_users: bool,
+    check: bool,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args::default();
+    let mut argv = env::args().skip(1);
+
+    while let Some(arg) = argv.next() {
+        let mut value = || {
+            argv.next()
+                .ok_or_else(|| format!("{} requires a value", arg))
+        };
+
+        match arg.as_str() {
+            "-c" | "--config" => args.config = Some(value()?.into()),
+            "-l" | "--listen" => args.listen.push(value()?),
+            "-r" | "--root" => args.root = Some(value()?.into()),
+            "--passive-ports" => args.passive_ports = Some(value()?),
+            "--banner" => args.banner = Some(value()?),
+            "--log-level" => args.log_level = Some(value()?),
+            "--metrics" => args.metrics = Some(value()?),
+            "--insecure-test-users" => args.test_users = true,
+            "--check" => args.check = true,
+            "-h" | "--help" => {
+                print!("{}", USAGE);
+                process::exit(0);
+            }
+            _ => return Err(format!("unrecognized argument {:?}", arg)),
+        }
+    }
+
+    Ok(args)
+}
+
+/// Loads the config file, if any, and applies command line overrides to it
+fn settings(args: &Args) -> Result<Settings, String> {
+    let mut settings = match &args.config {
+        Some(path) => Settings::load(path).map_err(|e| e.to_string())?,
+        None => Settings::default(),
+    };
+
+    if !args.listen.is_empty() {
+        settings.listen = args.listen.clone();
+    }
+    if args.root.is_some() {
+        settings.root = args.root.clone();
+    }
+    if args.passive_ports.is_some() {
+        settings.passive_ports = args.passive_ports.clone();
+    }
+    if args.banner.is_some() {
+        settings.banner = args.banner.clone();
+        settings.banner_file = None;
+    }
+    if args.log_level.is_some() {
+        settings.log.level = args.log_level.clone();
+    }
+    if args.metrics.is_some() {
+        settings.metrics = args.metrics.clone();
+    }
+
+    if settings.listen.is_empty() {
+        settings.listen.push(DEFAULT_LISTEN.to_owned());
+    }
+
+    Ok(settings)
+}
+
+fn config(settings: &Settings, test_users_allowed: bool) -> Result<Config, String> {
+    let mut users = settings.users().map_err(|e| e.to_string())?;
+
+    if test_users_allowed {
+        for (name, user) in test_users() {
+            users.entry(name).or_insert(user);
+        }
+    }
+
+    if users.is_empty() {
+        return Err(
+            "no users are configured. Add some to the config file, or pass \
+                    --insecure-test-users to accept the built-in test accounts"
+                .to_owned(),
+        );
+    }
+
+    settings
+        .apply(Config::new(users))
+        .map_err(|e| e.to_string())
+}
+
+/// Reloads the config file, and reapplies the command line, whenever the
+/// process receives `SIGHUP`
+#[cfg(unix)]
+fn reload_on_hangup(args: Args, started: Settings, handle: ServerHandle) -> io::Result<()> {
+    use log::{error, info, warn};
+    use signal_hook::{consts::SIGHUP, iterator::Signals};
+
+    let mut signals = Signals::new([SIGHUP])?;
+
+    std::thread::spawn(move || {
+        for _ in signals.forever() {
+            info!("Received SIGHUP, reloading configuration");
+
+            let reloaded = settings(&args)
+                .and_then(|settings| Ok((config(&settings, args.test_users)?, settings)));
+
+            match reloaded {
+                Ok((config, settings)) => {
+                    if settings.listen != started.listen
+                        || settings.root != started.root
+                        || settings.metrics != started.metrics
+                    {
+                        warn!("Changes to listen, root and metrics take effect after a restart");
+                    }
+
+                    handle.reload(config);
+                }
+                Err(e) => error!("Keeping the current configuration: {}", e),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn reload_on_hangup(_args: Args, _started: Settings, _handle: ServerHandle) -> io::Result<()> {
+    Ok(())
+}
+
+fn run() -> Result<(), String> {
+    let args = parse_args()?;
+    let settings = settings(&args)?;
+
+    env_logger::Builder::from_env(
+        env_logger::Env::default()
+            .default_filter_or(settings.log.level.as_deref().unwrap_or("warn")),
     )
-    .run()
+    .init();
+
+    let root = settings
+        .root
+        .clone()
+        .ok_or("no root directory is configured")?;
+
+    if !root.is_dir() {
+        return Err(format!("root {:?} is not a directory", root));
+    }
+
+    let config = config(&settings, args.test_users)?;
+
+    if args.check {
+        println!("Configuration is valid.");
+        return Ok(());
+    }
+
+    let (first, rest) = settings.listen.split_first().expect("defaulted above");
+
+    let mut server = Server::bind(first.as_str(), config, root)
+        .map_err(|e| format!("unable to listen on {}: {}", first, e))?;
+
+    for addr in rest {
+        server
+            .listen(addr.as_str())
+            .map_err(|e| format!("unable to listen on {}: {}", addr, e))?;
+    }
+
+    if let Some(addr) = &settings.metrics {
+        server
+            .handle()
+            .serve_metrics(addr.as_str())
+            .map_err(|e| format!("unable to serve metrics on {}: {}", addr, e))?;
+    }
+
+    reload_on_hangup(args, settings, server.handle())
+        .map_err(|e| format!("unable to handle SIGHUP: {}", e))?;
+
+    server.run().map_err(|e| e.to_string())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("server: {}", e);
+        process::exit(1);
+    }
 }
@@ -0,0 +1,109 @@
+use std::{
+    fmt::Write as _,
+    fs, io,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::time::DateTime;
+
+/// Text sent to clients, either given directly or read from a file each
+/// time it is sent
+#[derive(Debug, Clone)]
+pub(crate) enum Message {
+    Text(String),
+    File(PathBuf),
+}
+
+impl Message {
+    pub fn load(&self) -> io::Result<String> {
+        match self {
+            Message::Text(text) => Ok(text.clone()),
+            Message::File(path) => read(path),
+        }
+    }
+}
+
+/// Reads a message file, normalizing its line endings and dropping trailing
+/// blank lines
+pub(crate) fn read(path: &Path) -> io::Result<String> {
+    let text = fs::read_to_string(path)?;
+
+    Ok(text.replace("\r\n", "\n").trim_end().to_owned())
+}
+
+/// Values substituted for wu-ftpd style `%` cookies in messages
+pub(crate) struct Cookies<'a> {
+    pub user: Option<&'a str>,
+    pub remote: IpAddr,
+    pub local: IpAddr,
+    pub cwd: &'a Path,
+    pub sessions: usize,
+    pub max_sessions: Option<usize>,
+}
+
+/// Replaces `%T` (time), `%U` (user), `%R` (remote address), `%L` (local
+/// address), `%C` (current directory), `%F` (free kilobytes), `%N` (current
+/// sessions), `%M` (maximum sessions) and `%%`. Unknown cookies are left as
+/// they are
+pub(crate) fn expand(text: &str, cookies: &Cookies<'_>) -> String {
+    let mut expanded = String::with_capacity(text.len());
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+
+        // writing to a `String` can't fail
+        let _ = match chars.next() {
+            Some('T') => write!(expanded, "{}", DateTime::from(SystemTime::now()).ctime()),
+            Some('U') => write!(expanded, "{}", cookies.user.unwrap_or("unknown")),
+            Some('R') => write!(expanded, "{}", cookies.remote),
+            Some('L') => write!(expanded, "{}", cookies.local),
+            Some('C') => write!(expanded, "{}", cookies.cwd.display()),
+            Some('F') => match free_space(cookies.cwd) {
+                Some(bytes) => write!(expanded, "{}", bytes / 1024),
+                None => write!(expanded, "unknown"),
+            },
+            Some('N') => write!(expanded, "{}", cookies.sessions),
+            Some('M') => match cookies.max_sessions {
+                Some(max) => write!(expanded, "{}", max),
+                None => write!(expanded, "unlimited"),
+            },
+            Some('%') => write!(expanded, "%"),
+            Some(other) => write!(expanded, "%{}", other),
+            None => write!(expanded, "%"),
+        };
+    }
+
+    expanded
+}
+
+/// Bytes available to unprivileged users on the filesystem holding `path`
+#[cfg(unix)]
+fn free_space(path: &Path) -> Option<u64> {
+    use std::{ffi::CString, mem::MaybeUninit, os::unix::ffi::OsStrExt};
+
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+
+    // SAFETY: `path` is NUL terminated and `stat` is only read once
+    // `statvfs` reports that it filled it in
+    let stat = unsafe {
+        if libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) != 0 {
+            return None;
+        }
+        stat.assume_init()
+    };
+
+    #[allow(clippy::unnecessary_cast)]
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+fn free_space(_path: &Path) -> Option<u64> {
+    None
+}
@@ -0,0 +1,276 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Write as _},
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Mutex, MutexGuard},
+    time::Duration,
+};
+
+use log::debug;
+
+use crate::{throttle::Direction, Code};
+
+/// Upper bounds of the transfer duration histogram buckets, in seconds
+const DURATION_BUCKETS: [f64; 8] = [0.01, 0.1, 0.5, 1.0, 5.0, 30.0, 120.0, 600.0];
+
+#[derive(Debug, Default, Clone)]
+struct Histogram {
+    /// Observations falling at or below each of [`DURATION_BUCKETS`]
+    buckets: [u64; DURATION_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (bucket, &bound) in self.buckets.iter_mut().zip(DURATION_BUCKETS.iter()) {
+            if value <= bound {
+                *bucket += 1;
+            }
+        }
+
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    logins: BTreeMap<&'static str, u64>,
+    commands: BTreeMap<(String, &'static str), u64>,
+    bytes: BTreeMap<&'static str, u64>,
+    durations: BTreeMap<&'static str, Histogram>,
+    data_connection_failures: u64,
+}
+
+/// Counters updated by every connection, rendered in the Prometheus text
+/// exposition format
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    state: Mutex<State>,
+}
+
+fn direction(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Download => "download",
+        Direction::Upload => "upload",
+    }
+}
+
+fn reply_class(reply: Option<Code>) -> &'static str {
+    match reply.map(|code| code as u16 / 100) {
+        Some(1) => "1xx",
+        Some(2) => "2xx",
+        Some(3) => "3xx",
+        Some(4) => "4xx",
+        Some(5) => "5xx",
+        _ => "none",
+    }
+}
+
+impl Metrics {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn login(&self, success: bool) {
+        let result = if success { "success" } else { "failure" };
+
+        *self.state().logins.entry(result).or_insert(0) += 1;
+    }
+
+    /// Counts a command by its verb and the class of its final reply
+    pub fn command(&self, verb: &str, reply: Option<Code>) {
+        // arbitrary client input would otherwise create unbounded series
+        let verb = if reply == Some(Code::CommandUnrecognized) {
+            "unknown"
+        } else {
+            verb
+        };
+
+        *self
+            .state()
+            .commands
+            .entry((verb.to_owned(), reply_class(reply)))
+            .or_insert(0) += 1;
+    }
+
+    pub fn transfer(&self, dir: Direction, bytes: u64, duration: Duration) {
+        let mut state = self.state();
+
+        *state.bytes.entry(direction(dir)).or_insert(0) += bytes;
+        state
+            .durations
+            .entry(direction(dir))
+            .or_default()
+            .observe(duration.as_secs_f64());
+    }
+
+    pub fn data_connection_failure(&self) {
+        self.state().data_connection_failures += 1;
+    }
+
+    pub fn render(&self, active_sessions: usize) -> String {
+        let mut out = String::new();
+
+        // writing to a `String` can't fail
+        let _ = self.write(&mut out, active_sessions);
+
+        out
+    }
+
+    fn write(&self, out: &mut String, active_sessions: usize) -> fmt::Result {
+        let state = self.state();
+
+        writeln!(out, "# HELP ftp_sessions_active Open control connections.")?;
+        writeln!(out, "# TYPE ftp_sessions_active gauge")?;
+        writeln!(out, "ftp_sessions_active {}", active_sessions)?;
+
+        writeln!(out, "# HELP ftp_logins_total Login attempts by result.")?;
+        writeln!(out, "# TYPE ftp_logins_total counter")?;
+        for (result, count) in &state.logins {
+            writeln!(out, "ftp_logins_total{{result=\"{}\"}} {}", result, count)?;
+        }
+
+        writeln!(
+            out,
+            "# HELP ftp_commands_total Commands by verb and reply class."
+        )?;
+        writeln!(out, "# TYPE ftp_commands_total counter")?;
+        for ((verb, class), count) in &state.commands {
+            writeln!(
+                out,
+                "ftp_commands_total{{verb=\"{}\",class=\"{}\"}} {}",
+                escape(verb),
+                class,
+                count
+            )?;
+        }
+
+        writeln!(
+            out,
+            "# HELP ftp_transfer_bytes_total Bytes transferred by direction."
+        )?;
+        writeln!(out, "# TYPE ftp_transfer_bytes_total counter")?;
+        for (direction, bytes) in &state.bytes {
+            writeln!(
+                out,
+                "ftp_transfer_bytes_total{{direction=\"{}\"}} {}",
+                direction, bytes
+            )?;
+        }
+
+        writeln!(
+            out,
+            "# HELP ftp_transfer_duration_seconds File transfer durations."
+        )?;
+        writeln!(out, "# TYPE ftp_transfer_duration_seconds histogram")?;
+        for (direction, histogram) in &state.durations {
+            for (bound, count) in DURATION_BUCKETS.iter().zip(histogram.buckets.iter()) {
+                writeln!(
+                    out,
+                    "ftp_transfer_duration_seconds_bucket{{direction=\"{}\",le=\"{}\"}} {}",
+                    direction, bound, count
+                )?;
+            }
+            writeln!(
+                out,
+                "ftp_transfer_duration_seconds_bucket{{direction=\"{}\",le=\"+Inf\"}} {}",
+                direction, histogram.count
+            )?;
+            writeln!(
+                out,
+                "ftp_transfer_duration_seconds_sum{{direction=\"{}\"}} {}",
+                direction, histogram.sum
+            )?;
+            writeln!(
+                out,
+                "ftp_transfer_duration_seconds_count{{direction=\"{}\"}} {}",
+                direction, histogram.count
+            )?;
+        }
+
+        writeln!(
+                out,
+                "# HELP ftp_data_connection_failures_total Data connections which could not be opened or failed mid-transfer."
+            )?;
+        writeln!(out, "# TYPE ftp_data_connection_failures_total counter")?;
+        writeln!(
+            out,
+            "ftp_data_connection_failures_total {}",
+            state.data_connection_failures
+        )
+    }
+}
+
+/// Escapes a label value
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Serves `GET /metrics` on `listener` until it fails, rendering the body
+/// with `render` for each request
+pub(crate) fn serve<F: Fn() -> String>(listener: TcpListener, render: F) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                debug!("Metrics listener failed: {}", e);
+                return;
+            }
+        };
+
+        if let Err(e) = respond(stream, &render) {
+            debug!("Error serving metrics: {}", e);
+        }
+    }
+}
+
+fn respond<F: Fn() -> String>(stream: TcpStream, render: &F) -> io::Result<()> {
+    // requests are served one at a time, so don't let a slow client stall
+    // the rest
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(5)))?;
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    // skip the headers
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => (
+            "200 OK",
+            "text/plain; version=0.0.4; charset=utf-8",
+            render(),
+        ),
+        (Some("GET"), Some(_)) => ("404 Not Found", "text/plain", "Not found.\n".to_owned()),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            "Method not allowed.\n".to_owned(),
+        ),
+    };
+
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
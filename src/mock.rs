@@ -1,13 +1,15 @@
 use std::{
     collections::BTreeMap,
-    io::{BufReader, Read, Write},
+    env, fs,
+    io::{BufRead, BufReader, Read, Write},
     net::TcpStream,
-    path::PathBuf,
+    ops::Deref,
+    path::{Path, PathBuf},
     sync::atomic::{AtomicU16, Ordering},
     thread,
 };
 
-use crate::{Config, Server, Users};
+use crate::{Config, Server, ServerHandle, User, Users};
 
 const LOCALHOST: &str = "127.0.0.1";
 
@@ -19,46 +21,132 @@ static MOCK_COUNT: AtomicU16 = AtomicU16::new(60_000);
 pub struct MockFtpServer {
     reader: BufReader<TcpStream>,
     writer: TcpStream,
+    port: u16,
+    handle: ServerHandle,
 }
 
 pub fn test_users() -> Users {
     let mut users = BTreeMap::new();
-    users.insert("a".to_owned(), "a".to_owned());
-    users.insert("b".to_owned(), "b".to_owned());
+    users.insert("a".to_owned(), User::new("a"));
+    users.insert("b".to_owned(), User::new("b"));
     users
 }
 
+/// A path in the temporary directory, unique to this test process, which is
+/// removed when dropped so that a failing test doesn't leave it behind
+pub struct Scratch {
+    path: PathBuf,
+}
+
+/// A scratch path for a file named after `name`, which isn't created
+pub fn scratch_file(name: &str) -> Scratch {
+    Scratch {
+        path: env::temp_dir().join(format!("ftp-{}-{}", name, std::process::id())),
+    }
+}
+
+/// A scratch directory named after `name`, created empty
+pub fn scratch_dir(name: &str) -> Scratch {
+    let scratch = scratch_file(name);
+    let _ = fs::remove_dir_all(&scratch.path);
+    fs::create_dir_all(&scratch.path).unwrap();
+    scratch
+}
+
+impl Deref for Scratch {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for Scratch {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        if self.path.is_dir() {
+            let _ = fs::remove_dir_all(&self.path);
+        } else {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
 impl MockFtpServer {
-    /// Creates a new server bound to localhost on a unique port
+    /// Creates a new server bound to localhost on a unique port, logged in as
+    /// user `a`
     pub fn new() -> Self {
+        Self::with_config(Config::new(test_users()))
+    }
+
+    /// Creates a new server using `config`, logged in as user `a`
+    pub fn with_config(config: Config) -> Self {
+        let mut server = Self::unauthenticated(config);
+
+        server.login("a", "a");
+
+        server
+    }
+
+    /// Creates a new server using `config`, stopping after the greeting
+    pub fn unauthenticated(config: Config) -> Self {
+        let mut server = Self::start(config);
+
+        server.assert_output(b"220 Server ready for new user.\r\n");
+
+        server
+    }
+
+    /// Creates a new server using `config` and connects to it, without
+    /// reading the greeting
+    pub fn start(config: Config) -> Self {
         let port = MOCK_COUNT.fetch_add(1, Ordering::Relaxed);
 
-        thread::spawn(move || {
-            Server::new(
-                (LOCALHOST, port),
-                Config::new(test_users()),
-                PathBuf::from("."),
-            )
-            .run()
-        });
+        // bind before spawning so that we never race the listener
+        let server = Server::new((LOCALHOST, port), config, PathBuf::from("."));
+        let handle = server.handle();
+
+        thread::spawn(move || server.run());
+
+        Self::connect_to(port, handle)
+    }
 
+    fn connect_to(port: u16, handle: ServerHandle) -> Self {
         let connection = TcpStream::connect((LOCALHOST, port)).unwrap();
 
         let writer = connection.try_clone().unwrap();
         let reader = BufReader::new(connection);
 
-        let mut server = MockFtpServer { writer, reader };
+        MockFtpServer {
+            writer,
+            reader,
+            port,
+            handle,
+        }
+    }
 
-        server.assert_output(b"220 Server ready for new user.\r\n");
-        server.assert_output(b"332 Enter username.\r\n");
+    /// Opens another control connection to the same server, without reading
+    /// the greeting
+    pub fn connect(&self) -> Self {
+        Self::connect_to(self.port, self.handle.clone())
+    }
 
-        server.send_bytes(b"USER a\r\n");
-        server.assert_output(b"331 Username Ok. Password needed.\r\n");
+    pub fn handle(&self) -> &ServerHandle {
+        &self.handle
+    }
 
-        server.send_bytes(b"PASS a\r\n");
-        server.assert_output(b"230 Logged in.\r\n");
+    /// Logs in, asserting that the login succeeded
+    pub fn login(&mut self, username: &str, password: &str) {
+        self.send_bytes(format!("USER {}\r\n", username).as_bytes());
+        self.assert_output(b"331 Username Ok. Password needed.\r\n");
 
-        server
+        self.send_bytes(format!("PASS {}\r\n", password).as_bytes());
+        self.assert_output(b"230 Logged in.\r\n");
     }
 
     /// Sends all bytes given, panicking if sending failed
@@ -66,6 +154,62 @@ impl MockFtpServer {
         self.writer.write_all(bytes).unwrap()
     }
 
+    /// Reads a single reply line, including the trailing CRLF
+    pub fn read_line(&mut self) -> String {
+        let mut line = String::new();
+
+        self.reader.read_line(&mut line).unwrap();
+
+        line
+    }
+
+    /// Sends `PASV` and connects to the port the server replies with
+    pub fn pasv(&mut self) -> TcpStream {
+        self.send_bytes(b"PASV\r\n");
+
+        let line = self.read_line();
+
+        assert!(line.starts_with("227 "), "unexpected reply: {:?}", line);
+
+        let start = line.find('(').unwrap() + 1;
+        let end = line.find(')').unwrap();
+
+        let fields: Vec<u16> = line[start..end]
+            .split(',')
+            .map(|field| field.parse().unwrap())
+            .collect();
+
+        TcpStream::connect((LOCALHOST, (fields[4] << 8) + fields[5])).unwrap()
+    }
+
+    /// Downloads `path` over a passive data connection
+    pub fn retr(&mut self, path: &str) -> Vec<u8> {
+        let mut data_connection = self.pasv();
+
+        self.send_bytes(format!("RETR {}\r\n", path).as_bytes());
+        self.assert_output(b"150 Connecting to data port.\r\n");
+
+        let mut data = Vec::new();
+        data_connection.read_to_end(&mut data).unwrap();
+
+        self.assert_output(b"226 Closing connection\r\n");
+
+        data
+    }
+
+    /// Uploads `data` to `path` over a passive data connection
+    pub fn stor(&mut self, path: &str, data: &[u8]) {
+        let mut data_connection = self.pasv();
+
+        self.send_bytes(format!("STOR {}\r\n", path).as_bytes());
+        self.assert_output(b"150 Connecting to data port.\r\n");
+
+        data_connection.write_all(data).unwrap();
+        drop(data_connection);
+
+        self.assert_output(b"226 Closing connection\r\n");
+    }
+
     pub fn assert_output(&mut self, output: &[u8]) {
         let mut output_buf = vec![0; output.len()];
 
@@ -74,7 +218,20 @@ impl MockFtpServer {
         assert_eq!(output, output_buf.as_slice())
     }
 
+    /// Asserts that the server has closed the control connection
+    pub fn assert_closed(&mut self) {
+        let mut buf = [0];
+
+        assert_eq!(self.reader.read(&mut buf).unwrap(), 0);
+    }
+
     pub fn quit(mut self) {
         self.send_bytes(b"QUIT\r\n")
     }
 }
+
+impl Default for MockFtpServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,389 @@
+//! Page structure (`STRU P`), which keeps the holes in sparse files.
+//!
+//! Every field of a page header is one 8-bit logical byte, which limits a
+//! page to [`PAGE_SIZE`] bytes of data. Page `n` of a file holds the bytes
+//! from `n * PAGE_SIZE`, and is sent with the index `n % 256`. Pages lying
+//! entirely within a hole aren't sent, and the receiver takes a jump in the
+//! index to mean the pages between were holes. Where a hole spans 256
+//! pages or more, the sender sends an empty page to keep the jump
+//! unambiguous. The last page of a file is always sent, so that its length
+//! survives.
+//!
+//! Page data is the file as stored, whatever the TYPE, so only 8-bit binary
+//! types may be used with it
+
+use std::{
+    error::Error,
+    fmt,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+};
+
+use log::debug;
+
+use crate::data::PageType;
+
+/// The most data a page can hold, as its length must fit in a logical byte
+const PAGE_SIZE: u64 = 255;
+
+/// Pages are numbered modulo this on the data connection
+const INDEX_MODULUS: u64 = 256;
+
+/// Returned from [`PageWriter`] when the client sends a page type it
+/// doesn't know
+#[derive(Debug)]
+pub(crate) struct PageTypeUnknown(pub u8);
+
+impl fmt::Display for PageTypeUnknown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown page type {}", self.0)
+    }
+}
+
+impl Error for PageTypeUnknown {}
+
+impl PageTypeUnknown {
+    /// The page type which caused `e`, if any
+    pub fn caused(e: &io::Error) -> Option<u8> {
+        e.get_ref()
+            .and_then(|inner| inner.downcast_ref::<PageTypeUnknown>())
+            .map(|unknown| unknown.0)
+    }
+}
+
+/// The header which starts each page
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct PageHeader {
+    index: u8,
+    data_length: u8,
+    page_type: PageType,
+    /// Only present on [`PageType::AccessControlled`] pages
+    access_control: Option<u8>,
+}
+
+impl PageHeader {
+    fn encode(&self, output: &mut Vec<u8>) {
+        output.extend_from_slice(&[
+            self.page_type.header_length(),
+            self.index,
+            self.data_length,
+            self.page_type as u8,
+        ]);
+        output.extend(self.access_control);
+    }
+
+    /// Reads a complete header, which may be longer than its type needs
+    fn decode(header: &[u8]) -> io::Result<Self> {
+        let page_type = PageType::from_u8(header[3])
+            .ok_or_else(|| io::Error::other(PageTypeUnknown(header[3])))?;
+
+        if header.len() < page_type.header_length() as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "page header of {} bytes is too short for {:?} pages",
+                    header.len(),
+                    page_type
+                ),
+            ));
+        }
+
+        Ok(Self {
+            index: header[1],
+            data_length: header[2],
+            page_type,
+            access_control: match page_type {
+                PageType::AccessControlled => Some(header[4]),
+                _ => None,
+            },
+        })
+    }
+}
+
+/// Reads a local file as a series of pages, leaving out its holes
+pub(crate) struct PagedFile {
+    file: File,
+    len: u64,
+    /// The next page to send
+    page: u64,
+    /// The last page sent
+    sent: Option<u64>,
+    /// The extent of data most recently found, from where it starts to the
+    /// hole after it
+    data: (u64, u64),
+    pages: Vec<u8>,
+    position: usize,
+    finished: bool,
+}
+
+impl PagedFile {
+    pub fn new(file: File) -> io::Result<Self> {
+        let len = file.metadata()?.len();
+
+        Ok(Self {
+            file,
+            len,
+            page: 0,
+            sent: None,
+            data: (0, 0),
+            pages: Vec::new(),
+            position: 0,
+            finished: false,
+        })
+    }
+
+    /// Whether any of the file from `start` to `end` is data rather than
+    /// hole
+    fn has_data(&mut self, start: u64, end: u64) -> io::Result<bool> {
+        if start >= self.data.1 {
+            self.data = next_data(&self.file, start, self.len)?;
+        }
+
+        Ok(self.data.0 < end)
+    }
+
+    fn push_page(&mut self, page: u64, page_type: PageType, data: &[u8]) {
+        PageHeader {
+            index: (page % INDEX_MODULUS) as u8,
+            data_length: data.len() as u8,
+            page_type,
+            access_control: None,
+        }
+        .encode(&mut self.pages);
+        self.pages.extend_from_slice(data);
+        self.sent = Some(page);
+    }
+
+    /// Encodes the next page with data, or the end of the file
+    fn fill(&mut self) -> io::Result<()> {
+        self.pages.clear();
+        self.position = 0;
+
+        let last = self.len.div_ceil(PAGE_SIZE);
+
+        while self.page < last {
+            let page = self.page;
+            let start = page * PAGE_SIZE;
+            let end = (start + PAGE_SIZE).min(self.len);
+            self.page += 1;
+
+            if page + 1 != last && !self.has_data(start, end)? {
+                // a jump of a whole cycle of indices would look like none
+                let since = self.sent.map_or(page + 1, |sent| page - sent);
+                if since == INDEX_MODULUS {
+                    self.push_page(page, PageType::Simple, &[]);
+                }
+                continue;
+            }
+
+            let mut data = vec![0; (end - start) as usize];
+            self.file.seek(SeekFrom::Start(start))?;
+            self.file.read_exact(&mut data)?;
+            self.push_page(page, PageType::Simple, &data);
+
+            return Ok(());
+        }
+
+        self.push_page(last, PageType::Last, &[]);
+        self.finished = true;
+
+        Ok(())
+    }
+}
+
+impl Read for PagedFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.pages.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            self.fill()?;
+        }
+
+        let len = buf.len().min(self.pages.len() - self.position);
+        buf[..len].copy_from_slice(&self.pages[self.position..self.position + len]);
+        self.position += len;
+
+        Ok(len)
+    }
+}
+
+/// The extent of data at or after `offset`, from where it starts to the
+/// hole which follows it. Past the last data, the extent is empty and
+/// starts at `len`
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+fn next_data(file: &File, offset: u64, len: u64) -> io::Result<(u64, u64)> {
+    use std::os::unix::io::AsRawFd;
+
+    let seek = |offset: u64, whence: libc::c_int| {
+        // SAFETY: the descriptor is owned by `file`. Only the offset changes,
+        // and every read seeks first
+        let result = unsafe { libc::lseek(file.as_raw_fd(), offset as libc::off_t, whence) };
+
+        if result >= 0 {
+            Ok(Some(result as u64))
+        } else {
+            match io::Error::last_os_error() {
+                e if e.raw_os_error() == Some(libc::ENXIO) => Ok(None),
+                e => Err(e),
+            }
+        }
+    };
+
+    let start = match seek(offset, libc::SEEK_DATA)? {
+        Some(start) => start,
+        None => return Ok((len, len)),
+    };
+    let end = seek(start, libc::SEEK_HOLE)?.unwrap_or(len);
+
+    Ok((start, end))
+}
+
+/// Without a way to find holes, the whole file is data
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+fn next_data(_file: &File, offset: u64, len: u64) -> io::Result<(u64, u64)> {
+    Ok((offset, len))
+}
+
+#[derive(Debug)]
+enum State {
+    /// Reading a header, which is complete once it reaches the length of
+    /// its first byte
+    Header(Vec<u8>),
+
+    /// Reading a page's data, of which `remaining` bytes are left
+    Data { remaining: u64, skip: bool },
+
+    /// The last page has been read
+    Done,
+}
+
+/// Writes pages received from the data connection into `file` at the
+/// offsets their indices give, seeking over the pages which aren't sent
+pub(crate) struct PageWriter<W> {
+    file: W,
+    state: State,
+    /// The last page read
+    page: Option<u64>,
+}
+
+impl<W> PageWriter<W> {
+    pub fn new(file: W) -> Self {
+        Self {
+            file,
+            state: State::Header(Vec::with_capacity(5)),
+            page: None,
+        }
+    }
+
+    /// Works out which page of the file has `index`, from the page before
+    fn locate(&mut self, index: u8) -> u64 {
+        let next = self.page.map_or(0, |page| page + 1);
+        let skipped = (index as u64 + INDEX_MODULUS - next % INDEX_MODULUS) % INDEX_MODULUS;
+        let page = next + skipped;
+
+        self.page = Some(page);
+        page
+    }
+}
+
+impl<W: Write + Seek> PageWriter<W> {
+    /// Starts the page described by `header`
+    fn start(&mut self, header: &[u8]) -> io::Result<()> {
+        let header = PageHeader::decode(header)?;
+        let remaining = header.data_length as u64;
+
+        self.state = match header.page_type {
+            PageType::Last => State::Done,
+            // describes the file as a whole, rather than being a page of it
+            PageType::Descriptor => {
+                debug!("Ignoring a file descriptor page.");
+                State::Data {
+                    remaining,
+                    skip: true,
+                }
+            }
+            PageType::Simple | PageType::AccessControlled => {
+                let page = self.locate(header.index);
+                if let Some(access_control) = header.access_control {
+                    debug!(
+                        "Ignoring access control {:#04x} of page {}",
+                        access_control, page
+                    );
+                }
+                self.file.seek(SeekFrom::Start(page * PAGE_SIZE))?;
+                State::Data {
+                    remaining,
+                    skip: false,
+                }
+            }
+        };
+
+        if let State::Data { remaining: 0, .. } = self.state {
+            self.state = State::Header(Vec::with_capacity(5));
+        }
+
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> Write for PageWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut input = buf;
+
+        while !input.is_empty() {
+            match &mut self.state {
+                State::Header(header) => {
+                    header.push(input[0]);
+                    input = &input[1..];
+
+                    if header[0] < 4 {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("page header length {} is less than 4", header[0]),
+                        ));
+                    }
+
+                    if header.len() == header[0] as usize {
+                        let header = std::mem::take(header);
+                        self.start(&header)?;
+                    }
+                }
+                State::Data { remaining, skip } => {
+                    let len = input.len().min(*remaining as usize);
+
+                    if !*skip {
+                        self.file.write_all(&input[..len])?;
+                    }
+                    input = &input[len..];
+                    *remaining -= len as u64;
+
+                    if *remaining == 0 {
+                        self.state = State::Header(Vec::with_capacity(5));
+                    }
+                }
+                // nothing follows the last page
+                State::Done => break,
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    /// Flushes the file. Called once the data connection has closed, so a
+    /// page which is still incomplete never will be
+    fn flush(&mut self) -> io::Result<()> {
+        match &self.state {
+            State::Header(header) if header.is_empty() => {}
+            State::Done => {}
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "data connection closed within a page",
+                ))
+            }
+        }
+
+        self.file.flush()
+    }
+}
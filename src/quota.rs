@@ -0,0 +1,235 @@
+use std::{
+    error::Error,
+    ffi::OsString,
+    fmt,
+    fs::{self, File},
+    io::{self, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use log::debug;
+
+/// A user's storage allowance. `None` means unlimited
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Quota {
+    pub max_bytes: Option<u64>,
+    pub max_files: Option<u64>,
+}
+
+impl Quota {
+    /// The number of bytes that may still be written, or `None` if unlimited
+    pub(crate) fn remaining_bytes(&self, usage: Usage) -> Option<u64> {
+        self.max_bytes.map(|max| max.saturating_sub(usage.bytes))
+    }
+
+    /// Whether one more file may be created
+    pub(crate) fn allows_new_file(&self, usage: Usage) -> bool {
+        self.max_files.is_none_or(|max| usage.files < max)
+    }
+}
+
+/// The storage used under a directory
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub(crate) struct Usage {
+    pub bytes: u64,
+    pub files: u64,
+}
+
+impl Usage {
+    /// Walks `dir`, totalling the size and number of regular files. Symlinks
+    /// are not followed
+    pub fn measure(dir: &Path) -> io::Result<Self> {
+        let mut usage = Usage::default();
+
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let metadata = entry.path().symlink_metadata()?;
+
+            if metadata.is_dir() {
+                let inner = Usage::measure(&entry.path())?;
+                usage.bytes += inner.bytes;
+                usage.files += inner.files;
+            } else if metadata.is_file() {
+                usage.bytes += metadata.len();
+                usage.files += 1;
+            }
+        }
+
+        Ok(usage)
+    }
+
+    pub fn report(&self, quota: &Quota) -> String {
+        format!(
+            "{} of {} bytes, {} of {} files used.",
+            self.bytes,
+            limit(quota.max_bytes),
+            self.files,
+            limit(quota.max_files)
+        )
+    }
+}
+
+fn limit(max: Option<u64>) -> String {
+    match max {
+        Some(max) => max.to_string(),
+        None => "unlimited".to_owned(),
+    }
+}
+
+/// Returned from [`QuotaWriter`] when an upload would exceed its allowance
+#[derive(Debug)]
+pub(crate) struct QuotaExceeded;
+
+impl fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("storage quota exceeded")
+    }
+}
+
+impl Error for QuotaExceeded {}
+
+impl QuotaExceeded {
+    /// Whether `e` was caused by exceeding a quota
+    pub fn caused(e: &io::Error) -> bool {
+        e.get_ref()
+            .is_some_and(|inner| inner.downcast_ref::<QuotaExceeded>().is_some())
+    }
+}
+
+/// An upload which fails any write that would take the file more than
+/// `allowance` bytes past where it started. A new upload is written beside
+/// the file it replaces, and only takes its place once complete, so
+/// exceeding the quota leaves the old file as it was. A restarted upload is
+/// cut back to its restart marker
+pub(crate) struct QuotaFile {
+    file: File,
+    path: PathBuf,
+    /// Where a new upload is written until it replaces `path`
+    upload: Option<PathBuf>,
+    /// Where a restarted upload began
+    start: u64,
+    allowance: Option<u64>,
+    position: u64,
+    /// The furthest the file has been written to
+    end: u64,
+}
+
+impl QuotaFile {
+    /// Starts a new upload, to replace whatever is at `path` once complete
+    pub fn create(path: PathBuf, allowance: Option<u64>) -> io::Result<Self> {
+        static UPLOADS: AtomicU64 = AtomicU64::new(0);
+
+        // a directory can't be replaced, as `File::create` would refuse
+        if path.is_dir() {
+            return Err(io::ErrorKind::IsADirectory.into());
+        }
+
+        let mut name = OsString::from(".");
+        name.push(path.file_name().unwrap_or_default());
+        name.push(format!(
+            ".upload-{}-{}",
+            process::id(),
+            UPLOADS.fetch_add(1, Ordering::Relaxed)
+        ));
+        let upload = path.with_file_name(name);
+
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&upload)?;
+
+        Ok(Self {
+            file,
+            path,
+            upload: Some(upload),
+            start: 0,
+            allowance,
+            position: 0,
+            end: 0,
+        })
+    }
+
+    /// Resumes an upload into `file`, which has been truncated to `start`
+    pub fn resume(file: File, path: PathBuf, start: u64, allowance: Option<u64>) -> Self {
+        Self {
+            file,
+            path,
+            upload: None,
+            start,
+            allowance,
+            position: start,
+            end: start,
+        }
+    }
+
+    /// Moves a new upload over the file it replaces
+    fn replace(&mut self) -> io::Result<()> {
+        match self.upload.take() {
+            Some(upload) => fs::rename(upload, &self.path),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Write for QuotaFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(allowance) = self.allowance {
+            let end = self.end.max(self.position + buf.len() as u64);
+            if end - self.start > allowance {
+                let discarded = match self.upload.take() {
+                    Some(upload) => {
+                        debug!("Removing partial upload {:?}", upload);
+                        fs::remove_file(upload)
+                    }
+                    None => {
+                        debug!("Truncating {:?} back to {}", self.path, self.start);
+                        self.file.set_len(self.start)
+                    }
+                };
+
+                if let Err(e) = discarded {
+                    debug!("Unable to discard the upload to {:?}: {}", self.path, e);
+                }
+
+                return Err(io::Error::other(QuotaExceeded));
+            }
+        }
+
+        let len = self.file.write(buf)?;
+        self.position += len as u64;
+        self.end = self.end.max(self.position);
+
+        Ok(len)
+    }
+
+    /// Flushes the file. Called once the upload is complete, when a new
+    /// upload replaces the file at its path
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.replace()
+    }
+}
+
+/// An upload which fails within the quota is kept, so that it can be
+/// restarted
+impl Drop for QuotaFile {
+    fn drop(&mut self) {
+        if let Err(e) = self.replace() {
+            debug!(
+                "Unable to keep the partial upload to {:?}: {}",
+                self.path, e
+            );
+        }
+    }
+}
+
+/// Seeking over part of a file leaves a hole. Usage is measured by length,
+/// so a hole counts against the quota once written past
+impl Seek for QuotaFile {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        self.position = self.file.seek(position)?;
+        Ok(self.position)
+    }
+}
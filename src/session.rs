@@ -0,0 +1,185 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+};
+
+/// Maximum numbers of concurrent sessions. `None` means unlimited
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Limits {
+    /// Total control connections across every client
+    pub max_sessions: Option<usize>,
+
+    /// Control connections originating from a single IP address
+    pub max_sessions_per_ip: Option<usize>,
+
+    /// Logged in sessions for a single user
+    pub max_sessions_per_user: Option<usize>,
+}
+
+/// The limit that would have been exceeded by accepting a session
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum LimitExceeded {
+    Total,
+    PerIp,
+    PerUser,
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LimitExceeded::Total => "Too many users, try again later.",
+            LimitExceeded::PerIp => "Too many connections from your address.",
+            LimitExceeded::PerUser => "Too many sessions for this user.",
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+    per_user: HashMap<String, usize>,
+}
+
+/// Counts of every open session, shared by the server and its connections
+#[derive(Debug, Default)]
+pub(crate) struct Sessions {
+    counts: Mutex<Counts>,
+    next_id: AtomicU64,
+}
+
+impl Sessions {
+    fn counts(&self) -> MutexGuard<'_, Counts> {
+        // a panicking connection thread should not take the server with it
+        self.counts.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Registers a new control connection from `remote`
+    pub fn open(
+        self: &Arc<Self>,
+        remote: SocketAddr,
+        limits: &Limits,
+    ) -> Result<Session, LimitExceeded> {
+        let ip = remote.ip();
+        let mut counts = self.counts();
+
+        if limits.max_sessions.is_some_and(|max| counts.total >= max) {
+            return Err(LimitExceeded::Total);
+        }
+
+        let from_ip = counts.per_ip.get(&ip).copied().unwrap_or(0);
+
+        if limits.max_sessions_per_ip.is_some_and(|max| from_ip >= max) {
+            return Err(LimitExceeded::PerIp);
+        }
+
+        counts.total += 1;
+        *counts.per_ip.entry(ip).or_insert(0) += 1;
+
+        Ok(Session {
+            sessions: Arc::clone(self),
+            id: self.next_id.fetch_add(1, Ordering::Relaxed) + 1,
+            remote,
+            user: None,
+        })
+    }
+
+    pub fn total(&self) -> usize {
+        self.counts().total
+    }
+
+    pub fn for_ip(&self, ip: IpAddr) -> usize {
+        self.counts().per_ip.get(&ip).copied().unwrap_or(0)
+    }
+
+    pub fn for_user(&self, user: &str) -> usize {
+        self.counts().per_user.get(user).copied().unwrap_or(0)
+    }
+}
+
+fn decrement<K: std::hash::Hash + Eq>(map: &mut HashMap<K, usize>, key: &K) {
+    if let Some(count) = map.get_mut(key) {
+        *count -= 1;
+        if *count == 0 {
+            map.remove(key);
+        }
+    }
+}
+
+/// A registered session, which is removed from the counts when dropped
+#[derive(Debug)]
+pub(crate) struct Session {
+    sessions: Arc<Sessions>,
+    id: u64,
+    remote: SocketAddr,
+    user: Option<String>,
+}
+
+impl Session {
+    /// Unique to this session for the life of the server, starting from 1
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// The address and port this session originates from
+    pub fn remote(&self) -> SocketAddr {
+        self.remote
+    }
+
+    /// The address this session originates from
+    pub fn ip(&self) -> IpAddr {
+        self.remote.ip()
+    }
+
+    /// Attributes this session to `user`, replacing any previous user
+    pub fn login(&mut self, user: &str, limits: &Limits) -> Result<(), LimitExceeded> {
+        let mut counts = self.sessions.counts();
+
+        if self.user.as_deref() == Some(user) {
+            return Ok(());
+        }
+
+        let for_user = counts.per_user.get(user).copied().unwrap_or(0);
+
+        if limits
+            .max_sessions_per_user
+            .is_some_and(|max| for_user >= max)
+        {
+            return Err(LimitExceeded::PerUser);
+        }
+
+        if let Some(previous) = self.user.take() {
+            decrement(&mut counts.per_user, &previous);
+        }
+
+        *counts.per_user.entry(user.to_owned()).or_insert(0) += 1;
+        self.user = Some(user.to_owned());
+
+        Ok(())
+    }
+
+    /// Stops attributing this session to its user
+    pub fn logout(&mut self) {
+        if let Some(user) = self.user.take() {
+            decrement(&mut self.sessions.counts().per_user, &user);
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let mut counts = self.sessions.counts();
+
+        counts.total -= 1;
+        decrement(&mut counts.per_ip, &self.remote.ip());
+
+        if let Some(user) = &self.user {
+            decrement(&mut counts.per_user, user);
+        }
+    }
+}
@@ -0,0 +1,393 @@
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt, fs, io,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use serde::Deserialize;
+
+use crate::{
+    AccessRules, AccountRequirement, Cidr, Config, Limits, Quota, RateLimits, Timeouts,
+    UnknownCodePage, User, Users,
+};
+
+/// The contents of a TOML configuration file for the `server` binary.
+///
+/// Every field is optional; anything left out keeps its library default
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    /// Addresses to accept control connections on
+    pub listen: Vec<String>,
+
+    /// The directory served to clients
+    pub root: Option<PathBuf>,
+
+    /// The 220 greeting
+    pub banner: Option<String>,
+
+    /// A file to read the 220 greeting from, instead of `banner`
+    pub banner_file: Option<PathBuf>,
+
+    /// Sent with the 230 reply to a successful login
+    pub login_message: Option<String>,
+
+    /// A file to read the login message from, instead of `login_message`
+    pub login_message_file: Option<PathBuf>,
+
+    /// Whether `.message` files are sent on `CWD`
+    pub directory_messages: Option<bool>,
+
+    /// Ports `PASV` may listen on, as `first-last`
+    pub passive_ports: Option<String>,
+
+    /// Address to serve Prometheus metrics on
+    pub metrics: Option<String>,
+
+    /// Whether open sessions adopt this file's settings when it is reloaded
+    pub refresh_sessions: bool,
+
+    /// A separate TOML file of `[users]`, relative to this file
+    pub users_file: Option<PathBuf>,
+
+    /// Directories `SMNT` can switch to by name, relative to `root`
+    pub mounts: BTreeMap<String, PathBuf>,
+
+    /// The code page for `TYPE E` transfers: `037` or `1047`
+    pub ebcdic_code_page: Option<String>,
+
+    /// Bytes sent between restart markers in block and compressed mode; 0
+    /// sends none
+    pub restart_marker_interval: Option<u64>,
+
+    pub users: BTreeMap<String, UserSettings>,
+    pub access: AccessSettings,
+    pub limits: LimitSettings,
+    pub rates: RateSettings,
+    pub timeouts: TimeoutSettings,
+    pub log: LogSettings,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UserSettings {
+    pub password: String,
+    pub home: Option<PathBuf>,
+    pub download_rate: Option<u64>,
+    pub upload_rate: Option<u64>,
+    pub quota_bytes: Option<u64>,
+    pub quota_files: Option<u64>,
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+    pub account: Option<String>,
+    /// When `account` is needed: `login` (the default) or `store`
+    pub account_required: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccessSettings {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitSettings {
+    pub max_sessions: Option<usize>,
+    pub max_sessions_per_ip: Option<usize>,
+    pub max_sessions_per_user: Option<usize>,
+}
+
+/// Bytes per second
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateSettings {
+    pub global: Option<u64>,
+    pub per_session: Option<u64>,
+}
+
+/// Seconds
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeoutSettings {
+    pub idle: Option<u64>,
+    pub min_idle: Option<u64>,
+    pub max_idle: Option<u64>,
+    pub data: Option<u64>,
+    pub passive_accept: Option<u64>,
+    pub login: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogSettings {
+    /// An `env_logger` filter such as `info` or `ftp=debug`
+    pub level: Option<String>,
+    pub xferlog: Option<PathBuf>,
+    pub audit: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UsersFile {
+    #[serde(default)]
+    users: BTreeMap<String, UserSettings>,
+}
+
+#[derive(Debug)]
+pub enum SettingsError {
+    Io(PathBuf, io::Error),
+    Parse(toml::de::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SettingsError::Io(path, e) => write!(f, "unable to read {:?}: {}", path, e),
+            SettingsError::Parse(e) => write!(f, "invalid configuration: {}", e),
+            SettingsError::Invalid(e) => write!(f, "invalid configuration: {}", e),
+        }
+    }
+}
+
+impl Error for SettingsError {}
+
+fn invalid<T, S: Into<String>>(message: S) -> Result<T, SettingsError> {
+    Err(SettingsError::Invalid(message.into()))
+}
+
+fn read(path: &Path) -> Result<String, SettingsError> {
+    fs::read_to_string(path).map_err(|e| SettingsError::Io(path.to_owned(), e))
+}
+
+fn access_rules(allow: &[String], deny: &[String]) -> Result<AccessRules, SettingsError> {
+    let mut rules = AccessRules::default();
+
+    for cidr in allow {
+        rules = rules.allow(parse_cidr(cidr)?);
+    }
+
+    for cidr in deny {
+        rules = rules.deny(parse_cidr(cidr)?);
+    }
+
+    Ok(rules)
+}
+
+fn parse_cidr(cidr: &str) -> Result<Cidr, SettingsError> {
+    cidr.parse()
+        .map_err(|e: crate::ParseCidrError| SettingsError::Invalid(e.to_string()))
+}
+
+impl FromStr for Settings {
+    type Err = SettingsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        toml::from_str(s).map_err(SettingsError::Parse)
+    }
+}
+
+impl Settings {
+    /// Reads `path`, along with its `users_file` if it names one
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SettingsError> {
+        let path = path.as_ref();
+        let mut settings: Settings = read(path)?.parse()?;
+
+        if let Some(users_file) = &settings.users_file {
+            let users_file = path
+                .parent()
+                .unwrap_or_else(|| Path::new(""))
+                .join(users_file);
+
+            let users: UsersFile =
+                toml::from_str(&read(&users_file)?).map_err(SettingsError::Parse)?;
+
+            for (name, user) in users.users {
+                if settings.users.contains_key(&name) {
+                    return invalid(format!("user {:?} is defined twice", name));
+                }
+                settings.users.insert(name, user);
+            }
+        }
+
+        Ok(settings)
+    }
+
+    /// The configured accounts
+    pub fn users(&self) -> Result<Users, SettingsError> {
+        let mut users = Users::new();
+
+        for (name, settings) in &self.users {
+            if settings.password.is_empty() {
+                return invalid(format!("user {:?} has no password", name));
+            }
+
+            let mut user = User::new(settings.password.clone())
+                .access(access_rules(&settings.allow, &settings.deny)?);
+
+            if let Some(home) = &settings.home {
+                user = user.home(home);
+            }
+            if let Some(rate) = settings.download_rate {
+                user = user.download_rate(rate);
+            }
+            if let Some(rate) = settings.upload_rate {
+                user = user.upload_rate(rate);
+            }
+            if settings.quota_bytes.is_some() || settings.quota_files.is_some() {
+                user = user.quota(Quota {
+                    max_bytes: settings.quota_bytes,
+                    max_files: settings.quota_files,
+                });
+            }
+
+            match (&settings.account, settings.account_required.as_deref()) {
+                (Some(account), required) => {
+                    let required = match required {
+                        None | Some("login") => AccountRequirement::Login,
+                        Some("store") => AccountRequirement::Store,
+                        Some(other) => {
+                            return invalid(format!(
+                                "user {:?} has invalid account_required {:?}",
+                                name, other
+                            ))
+                        }
+                    };
+                    user = user.account(account.clone(), required);
+                }
+                (None, Some(..)) => {
+                    return invalid(format!(
+                        "user {:?} sets account_required without an account",
+                        name
+                    ))
+                }
+                (None, None) => {}
+            }
+
+            users.insert(name.clone(), user);
+        }
+
+        Ok(users)
+    }
+
+    /// Parses `passive_ports`
+    pub fn passive_port_range(&self) -> Result<Option<RangeInclusive<u16>>, SettingsError> {
+        let ports = match &self.passive_ports {
+            Some(ports) => ports,
+            None => return Ok(None),
+        };
+
+        let err = || SettingsError::Invalid(format!("invalid passive port range {:?}", ports));
+
+        let mut bounds = ports.splitn(2, '-');
+        let first: u16 = bounds
+            .next()
+            .and_then(|port| port.trim().parse().ok())
+            .ok_or_else(err)?;
+        let last: u16 = match bounds.next() {
+            Some(port) => port.trim().parse().map_err(|_| err())?,
+            None => first,
+        };
+
+        if first == 0 || first > last {
+            return Err(err());
+        }
+
+        Ok(Some(first..=last))
+    }
+
+    fn timeouts(&self) -> Result<Timeouts, SettingsError> {
+        let mut timeouts = Timeouts::default();
+        let t = &self.timeouts;
+
+        for (value, field) in [
+            (t.idle, &mut timeouts.idle),
+            (t.min_idle, &mut timeouts.min_idle),
+            (t.max_idle, &mut timeouts.max_idle),
+            (t.data, &mut timeouts.data),
+            (t.passive_accept, &mut timeouts.passive_accept),
+            (t.login, &mut timeouts.login),
+        ] {
+            if let Some(secs) = value {
+                *field = Duration::from_secs(secs);
+            }
+        }
+
+        if timeouts.min_idle > timeouts.max_idle
+            || timeouts.idle < timeouts.min_idle
+            || timeouts.idle > timeouts.max_idle
+        {
+            return invalid("timeouts must satisfy min_idle <= idle <= max_idle");
+        }
+
+        Ok(timeouts)
+    }
+
+    /// Applies everything but the users, listen addresses and root to
+    /// `config`
+    pub fn apply(&self, mut config: Config) -> Result<Config, SettingsError> {
+        config = config
+            .timeouts(self.timeouts()?)
+            .limits(Limits {
+                max_sessions: self.limits.max_sessions,
+                max_sessions_per_ip: self.limits.max_sessions_per_ip,
+                max_sessions_per_user: self.limits.max_sessions_per_user,
+            })
+            .rate_limits(RateLimits {
+                global: self.rates.global,
+                per_session: self.rates.per_session,
+            })
+            .access(access_rules(&self.access.allow, &self.access.deny)?)
+            .refresh_sessions(self.refresh_sessions);
+
+        if let Some(code_page) = &self.ebcdic_code_page {
+            let code_page = code_page
+                .parse()
+                .map_err(|e: UnknownCodePage| SettingsError::Invalid(e.to_string()))?;
+            config = config.ebcdic_code_page(code_page);
+        }
+        if let Some(interval) = self.restart_marker_interval {
+            config = config.restart_marker_interval(interval);
+        }
+        for (name, path) in &self.mounts {
+            config = config.mount(name.clone(), path);
+        }
+
+        if let Some(ports) = self.passive_port_range()? {
+            config = config.passive_ports(ports);
+        }
+        match (&self.banner, &self.banner_file) {
+            (Some(..), Some(..)) => {
+                return invalid("only one of banner and banner_file may be set")
+            }
+            (Some(banner), None) => config = config.banner(banner.clone()),
+            (None, Some(path)) => config = config.banner_file(path),
+            (None, None) => {}
+        }
+        match (&self.login_message, &self.login_message_file) {
+            (Some(..), Some(..)) => {
+                return invalid("only one of login_message and login_message_file may be set")
+            }
+            (Some(message), None) => config = config.login_message(message.clone()),
+            (None, Some(path)) => config = config.login_message_file(path),
+            (None, None) => {}
+        }
+        if let Some(enabled) = self.directory_messages {
+            config = config.directory_messages(enabled);
+        }
+        if let Some(xferlog) = &self.log.xferlog {
+            config = config.xferlog(xferlog);
+        }
+        if let Some(audit) = &self.log.audit {
+            config = config.audit_log(audit);
+        }
+
+        Ok(config)
+    }
+}
@@ -0,0 +1,113 @@
+//! The subset of the Telnet protocol (RFC 854) which RFC 959 expects of an
+//! FTP control connection. Option negotiation is always refused, so the
+//! connection stays in the default NVT mode
+
+/// Interpret As Command
+pub(crate) const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+/// Subnegotiation Begin
+const SB: u8 = 250;
+/// Interrupt Process
+const IP: u8 = 244;
+/// Data Mark
+const DM: u8 = 242;
+/// Subnegotiation End
+const SE: u8 = 240;
+
+/// A Telnet command which the session may want to act on
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum Event {
+    /// `IAC IP`, which clients send ahead of `ABOR`
+    Interrupt,
+
+    /// `IAC DM`, which completes a Synch when sent as urgent data
+    Synch,
+}
+
+/// What a byte of input turned out to be
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum Output {
+    /// A byte of the command stream
+    Data(u8),
+
+    Event(Event),
+
+    /// A reply to option negotiation, to be sent straight back
+    Reply([u8; 3]),
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum State {
+    Data,
+    Command,
+    Negotiation(u8),
+    Subnegotiation,
+    SubnegotiationCommand,
+}
+
+/// Separates Telnet commands from the command stream, one byte at a time so
+/// that commands split across reads are handled
+#[derive(Debug)]
+pub(crate) struct Filter {
+    state: State,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self { state: State::Data }
+    }
+}
+
+impl Filter {
+    pub fn push(&mut self, byte: u8) -> Option<Output> {
+        let (state, output) = match (self.state, byte) {
+            (State::Data, IAC) => (State::Command, None),
+            (State::Data, _) => (State::Data, Some(Output::Data(byte))),
+
+            // an escaped 0xFF
+            (State::Command, IAC) => (State::Data, Some(Output::Data(IAC))),
+            (State::Command, DO | DONT | WILL | WONT) => (State::Negotiation(byte), None),
+            (State::Command, SB) => (State::Subnegotiation, None),
+            (State::Command, IP) => (State::Data, Some(Output::Event(Event::Interrupt))),
+            (State::Command, DM) => (State::Data, Some(Output::Event(Event::Synch))),
+            // NOP, GA and the rest mean nothing to FTP
+            (State::Command, _) => (State::Data, None),
+
+            (State::Negotiation(verb), option) => {
+                let reply = match verb {
+                    DO => Some(Output::Reply([IAC, WONT, option])),
+                    WILL => Some(Output::Reply([IAC, DONT, option])),
+                    // we never enable an option, so there is nothing to
+                    // acknowledge
+                    _ => None,
+                };
+                (State::Data, reply)
+            }
+
+            (State::Subnegotiation, IAC) => (State::SubnegotiationCommand, None),
+            (State::Subnegotiation, _) => (State::Subnegotiation, None),
+            (State::SubnegotiationCommand, SE) => (State::Data, None),
+            (State::SubnegotiationCommand, _) => (State::Subnegotiation, None),
+        };
+
+        self.state = state;
+        output
+    }
+}
+
+/// Doubles every 0xFF in `bytes`, so the client doesn't take it for `IAC`
+pub(crate) fn escape(bytes: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(bytes.len());
+
+    for &byte in bytes {
+        if byte == IAC {
+            escaped.push(IAC);
+        }
+        escaped.push(byte);
+    }
+
+    escaped
+}
@@ -0,0 +1,176 @@
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Transfer rate limits, in bytes per second. `None` means unlimited
+#[derive(Debug, Copy, Clone, Default)]
+pub struct RateLimits {
+    /// Shared fairly between every transfer on the server
+    pub global: Option<u64>,
+
+    /// Applied to each control connection's transfers
+    pub per_session: Option<u64>,
+}
+
+/// The direction data is flowing, from the server's point of view
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub(crate) enum Direction {
+    Download,
+    Upload,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+/// A token bucket holding at most one second's worth of bytes.
+///
+/// Callers which want more tokens than are available go into debt and sleep
+/// until it is repaid, so concurrent transfers are served in the order they
+/// asked
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    rate: u64,
+    bucket: Mutex<Bucket>,
+}
+
+impl TokenBucket {
+    pub fn new(rate: u64) -> Self {
+        let rate = rate.max(1);
+
+        Self {
+            rate,
+            bucket: Mutex::new(Bucket {
+                tokens: rate as f64,
+                last: Instant::now(),
+            }),
+        }
+    }
+
+    /// The largest amount that should be requested at once
+    pub fn burst(&self) -> usize {
+        self.rate as usize
+    }
+
+    fn bucket(&self) -> MutexGuard<'_, Bucket> {
+        self.bucket.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Blocks until `amount` bytes may be transferred
+    pub fn take(&self, amount: usize) {
+        let wait = {
+            let mut bucket = self.bucket();
+            let now = Instant::now();
+
+            let refill = now.duration_since(bucket.last).as_secs_f64() * self.rate as f64;
+            bucket.tokens = (bucket.tokens + refill).min(self.rate as f64);
+            bucket.last = now;
+
+            bucket.tokens -= amount as f64;
+
+            if bucket.tokens >= 0.0 {
+                return;
+            }
+
+            Duration::from_secs_f64(-bucket.tokens / self.rate as f64)
+        };
+
+        thread::sleep(wait);
+    }
+}
+
+/// Token buckets which outlive a single session
+#[derive(Debug, Default)]
+pub(crate) struct Throttles {
+    global: Mutex<Option<Arc<TokenBucket>>>,
+    users: Mutex<HashMap<(String, Direction), Arc<TokenBucket>>>,
+}
+
+impl Throttles {
+    /// The bucket shared by every transfer, created on first use
+    pub fn global(&self, rate: Option<u64>) -> Option<Arc<TokenBucket>> {
+        let rate = rate?;
+        let mut global = self.global.lock().unwrap_or_else(|e| e.into_inner());
+
+        match &*global {
+            Some(bucket) if bucket.rate == rate => Some(Arc::clone(bucket)),
+            _ => Some(Arc::clone(global.insert(Arc::new(TokenBucket::new(rate))))),
+        }
+    }
+
+    /// The bucket shared by every session of `user` in one direction
+    pub fn user(
+        &self,
+        user: &str,
+        direction: Direction,
+        rate: Option<u64>,
+    ) -> Option<Arc<TokenBucket>> {
+        let rate = rate?;
+        let mut users = self.users.lock().unwrap_or_else(|e| e.into_inner());
+
+        let bucket = users
+            .entry((user.to_owned(), direction))
+            .or_insert_with(|| Arc::new(TokenBucket::new(rate)));
+
+        if bucket.rate != rate {
+            *bucket = Arc::new(TokenBucket::new(rate));
+        }
+
+        Some(Arc::clone(bucket))
+    }
+}
+
+/// Wraps a reader or writer, limiting it to the slowest of `buckets`
+pub(crate) struct Throttled<S> {
+    inner: S,
+    buckets: Vec<Arc<TokenBucket>>,
+}
+
+impl<S> Throttled<S> {
+    pub fn new(inner: S, buckets: Vec<Arc<TokenBucket>>) -> Self {
+        Self { inner, buckets }
+    }
+
+    /// The most that should be transferred at once, given every bucket's
+    /// burst
+    fn limit(&self, wanted: usize) -> usize {
+        self.buckets
+            .iter()
+            .map(|bucket| bucket.burst())
+            .fold(wanted, usize::min)
+    }
+
+    /// Takes `amount` bytes from every bucket, once they've been transferred
+    fn charge(&self, amount: usize) {
+        for bucket in &self.buckets {
+            bucket.take(amount);
+        }
+    }
+}
+
+impl<W: Write> Write for Throttled<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(&buf[..self.limit(buf.len())])?;
+        self.charge(len);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<R: Read> Read for Throttled<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let limit = self.limit(buf.len());
+        let len = self.inner.read(&mut buf[..limit])?;
+        self.charge(len);
+        Ok(len)
+    }
+}
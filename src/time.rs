@@ -0,0 +1,127 @@
+use std::{
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// A broken down UTC time, with millisecond precision
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) struct DateTime {
+    pub year: i64,
+    /// 1 through 12
+    pub month: u32,
+    /// 1 through 31
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    /// 0 is Sunday
+    pub weekday: u32,
+    pub millis: u32,
+}
+
+impl DateTime {
+    /// `Mon Oct 18 09:05:01 2026`, as produced by `ctime(3)`
+    pub fn ctime(&self) -> impl fmt::Display + '_ {
+        Ctime(self)
+    }
+
+    /// `Oct 18 09:05` if `recent`, and otherwise `Oct 18  2026`, as listed
+    /// by `ls -l`
+    pub fn ls(&self, recent: bool) -> impl fmt::Display + '_ {
+        Ls(self, recent)
+    }
+
+    /// `2026-10-18T09:05:01.123Z`
+    pub fn rfc3339(&self) -> impl fmt::Display + '_ {
+        Rfc3339(self)
+    }
+}
+
+impl From<SystemTime> for DateTime {
+    fn from(time: SystemTime) -> Self {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        let secs = since_epoch.as_secs() as i64;
+        let days = secs.div_euclid(86_400);
+        let secs_of_day = secs.rem_euclid(86_400) as u32;
+
+        // Howard Hinnant's `civil_from_days`
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        Self {
+            year,
+            month,
+            day,
+            hour: secs_of_day / 3600,
+            minute: secs_of_day / 60 % 60,
+            second: secs_of_day % 60,
+            // the epoch was a Thursday
+            weekday: (days + 4).rem_euclid(7) as u32,
+            millis: since_epoch.subsec_millis(),
+        }
+    }
+}
+
+struct Ctime<'a>(&'a DateTime);
+
+impl fmt::Display for Ctime<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let t = self.0;
+
+        write!(
+            f,
+            "{} {} {:2} {:02}:{:02}:{:02} {}",
+            WEEKDAYS[t.weekday as usize],
+            MONTHS[t.month as usize - 1],
+            t.day,
+            t.hour,
+            t.minute,
+            t.second,
+            t.year
+        )
+    }
+}
+
+struct Ls<'a>(&'a DateTime, bool);
+
+impl fmt::Display for Ls<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let t = self.0;
+
+        write!(f, "{} {:2} ", MONTHS[t.month as usize - 1], t.day)?;
+
+        if self.1 {
+            write!(f, "{:02}:{:02}", t.hour, t.minute)
+        } else {
+            write!(f, "{:>5}", t.year)
+        }
+    }
+}
+
+struct Rfc3339<'a>(&'a DateTime);
+
+impl fmt::Display for Rfc3339<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let t = self.0;
+
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            t.year, t.month, t.day, t.hour, t.minute, t.second, t.millis
+        )
+    }
+}
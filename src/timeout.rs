@@ -0,0 +1,38 @@
+use std::time::Duration;
+
+/// Deadlines applied to control and data connections
+#[derive(Debug, Copy, Clone)]
+pub struct Timeouts {
+    /// How long a control connection may go without receiving a command
+    /// before it is closed with a `421` reply
+    pub idle: Duration,
+
+    /// The smallest idle timeout a user may request with `SITE IDLE`
+    pub min_idle: Duration,
+
+    /// The largest idle timeout a user may request with `SITE IDLE`
+    pub max_idle: Duration,
+
+    /// How long a data transfer may go without making any progress
+    pub data: Duration,
+
+    /// How long to wait for the client to connect to the port handed out
+    /// by `PASV`
+    pub passive_accept: Duration,
+
+    /// How long a client has after connecting to complete `USER` and `PASS`
+    pub login: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            idle: Duration::from_secs(300),
+            min_idle: Duration::from_secs(30),
+            max_idle: Duration::from_secs(7200),
+            data: Duration::from_secs(300),
+            passive_accept: Duration::from_secs(60),
+            login: Duration::from_secs(60),
+        }
+    }
+}
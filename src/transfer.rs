@@ -0,0 +1,140 @@
+use std::{
+    io::{self, Read, Write},
+    mem,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use crate::data::Unconvertible;
+
+/// The size of each read from the source of a transfer
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// Which side of a transfer failed
+#[derive(Debug)]
+pub(crate) enum TransferError {
+    /// Reading or writing the local file failed
+    Local(io::Error),
+
+    /// Reading or writing the data connection failed
+    Network(io::Error),
+
+    /// The data connection sent data which couldn't be converted
+    Invalid(io::Error),
+
+    /// The client sent `ABOR`
+    Aborted,
+}
+
+/// The side a transfer's data connection is on
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum Side {
+    Source,
+    Sink,
+}
+
+/// How much of a transfer happened, and how it ended
+#[derive(Debug)]
+pub(crate) struct Transferred {
+    pub bytes: u64,
+    pub result: Result<(), TransferError>,
+}
+
+impl Transferred {
+    pub fn is_complete(&self) -> bool {
+        self.result.is_ok()
+    }
+}
+
+/// A running transfer's byte count, restart markers received and not yet
+/// reported, and a flag to stop it
+#[derive(Debug, Default)]
+pub(crate) struct Progress {
+    bytes: AtomicU64,
+    aborted: AtomicBool,
+    marks: Mutex<Vec<Mark>>,
+}
+
+/// A restart marker from the sender, and the position it corresponds to
+/// here
+#[derive(Debug)]
+pub(crate) struct Mark {
+    pub marker: String,
+    pub position: u64,
+}
+
+impl Progress {
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Stops the transfer before its next chunk
+    pub fn abort(&self) {
+        self.aborted.store(true, Ordering::Relaxed);
+    }
+
+    pub fn mark(&self, marker: String, position: u64) {
+        if let Ok(mut marks) = self.marks.lock() {
+            marks.push(Mark { marker, position });
+        }
+    }
+
+    /// The restart markers received since the last call
+    pub fn take_marks(&self) -> Vec<Mark> {
+        self.marks
+            .lock()
+            .map(|mut marks| mem::take(&mut *marks))
+            .unwrap_or_default()
+    }
+
+    fn is_aborted(&self) -> bool {
+        self.aborted.load(Ordering::Relaxed)
+    }
+}
+
+/// Copies `source` into `sink` until `source` is exhausted or `progress` is
+/// aborted. `network` says which of the two is the data connection, so that
+/// errors can be attributed correctly
+pub(crate) fn copy<R: Read, W: Write>(
+    source: &mut R,
+    sink: &mut W,
+    network: Side,
+    progress: &Progress,
+) -> Transferred {
+    let error = |side: Side, e: io::Error| {
+        if side != network {
+            TransferError::Local(e)
+        } else if Unconvertible::caused(&e) {
+            TransferError::Invalid(e)
+        } else {
+            TransferError::Network(e)
+        }
+    };
+
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut bytes = 0;
+
+    let result = loop {
+        if progress.is_aborted() {
+            break Err(TransferError::Aborted);
+        }
+
+        let len = match source.read(&mut buffer) {
+            Ok(0) => break sink.flush().map_err(|e| error(Side::Sink, e)),
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => break Err(error(Side::Source, e)),
+        };
+
+        if let Err(e) = sink.write_all(&buffer[..len]) {
+            break Err(error(Side::Sink, e));
+        }
+
+        bytes += len as u64;
+        progress.bytes.fetch_add(len as u64, Ordering::Relaxed);
+    };
+
+    Transferred { bytes, result }
+}
@@ -0,0 +1,80 @@
+use std::path::PathBuf;
+
+use crate::{cidr::AccessRules, quota::Quota};
+
+/// When a user must name their account with `ACCT`
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum AccountRequirement {
+    /// Before they are logged in
+    Login,
+
+    /// Before they may store files
+    Store,
+}
+
+/// An account which may log in
+#[derive(Debug, Clone)]
+pub struct User {
+    pub(crate) password: String,
+    pub(crate) access: AccessRules,
+    pub(crate) download_rate: Option<u64>,
+    pub(crate) upload_rate: Option<u64>,
+    pub(crate) home: Option<PathBuf>,
+    pub(crate) quota: Option<Quota>,
+    pub(crate) account: Option<(String, AccountRequirement)>,
+}
+
+impl User {
+    pub fn new<S: Into<String>>(password: S) -> Self {
+        Self {
+            password: password.into(),
+            access: AccessRules::default(),
+            download_rate: None,
+            upload_rate: None,
+            home: None,
+            quota: None,
+            account: None,
+        }
+    }
+
+    /// Restricts the addresses this user may log in from
+    pub fn access(mut self, access: AccessRules) -> Self {
+        self.access = access;
+        self
+    }
+
+    /// Limits this user's downloads, across all of their sessions, to
+    /// `bytes_per_second`
+    pub fn download_rate(mut self, bytes_per_second: u64) -> Self {
+        self.download_rate = Some(bytes_per_second);
+        self
+    }
+
+    /// Limits this user's uploads, across all of their sessions, to
+    /// `bytes_per_second`
+    pub fn upload_rate(mut self, bytes_per_second: u64) -> Self {
+        self.upload_rate = Some(bytes_per_second);
+        self
+    }
+
+    /// The directory this user starts in after logging in, relative to the
+    /// server root
+    pub fn home<P: Into<PathBuf>>(mut self, home: P) -> Self {
+        self.home = Some(home.into());
+        self
+    }
+
+    /// Limits the storage used under this user's home directory, or under
+    /// the server root if they have none
+    pub fn quota(mut self, quota: Quota) -> Self {
+        self.quota = Some(quota);
+        self
+    }
+
+    /// Requires this user to send `ACCT` with `account` before `required`
+    /// is allowed
+    pub fn account<S: Into<String>>(mut self, account: S, required: AccountRequirement) -> Self {
+        self.account = Some((account.into(), required));
+        self
+    }
+}
@@ -0,0 +1,76 @@
+use std::{
+    fmt,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use crate::{logfile::LogFile, throttle::Direction, time::DateTime};
+
+/// Appends wu-ftpd compatible `xferlog` records to a file
+#[derive(Debug)]
+pub(crate) struct Xferlog {
+    file: LogFile,
+}
+
+impl Xferlog {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            file: LogFile::new("xferlog", path),
+        }
+    }
+
+    pub fn record(&self, record: &Record<'_>) {
+        self.file.append(&record.to_string());
+    }
+}
+
+/// A single completed or aborted transfer
+#[derive(Debug)]
+pub(crate) struct Record<'a> {
+    pub time: SystemTime,
+    pub duration: Duration,
+    pub host: IpAddr,
+    pub bytes: u64,
+    pub path: &'a Path,
+    pub binary: bool,
+    pub direction: Direction,
+    pub username: &'a str,
+    pub complete: bool,
+}
+
+impl fmt::Display for Record<'_> {
+    /// `current-time transfer-time remote-host file-size filename
+    /// transfer-type special-action-flag direction access-mode username
+    /// service-name authentication-method authenticated-user-id
+    /// completion-status`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = field(&self.path.to_string_lossy());
+        let username = field(self.username);
+
+        write!(
+            f,
+            "{} {} {} {} {} {} _ {} r {} ftp 0 * {}",
+            DateTime::from(self.time).ctime(),
+            self.duration.as_secs_f64().round() as u64,
+            self.host,
+            self.bytes,
+            path,
+            if self.binary { 'b' } else { 'a' },
+            match self.direction {
+                Direction::Download => 'o',
+                Direction::Upload => 'i',
+            },
+            username,
+            if self.complete { 'c' } else { 'i' },
+        )
+    }
+}
+
+/// Fields are space separated and records end at a newline, so spaces and
+/// control characters in names can't be kept
+fn field(name: &str) -> String {
+    name.chars()
+        .map(|c| if c == ' ' || c.is_control() { '_' } else { c })
+        .collect()
+}
@@ -0,0 +1,105 @@
+use std::{
+    fs,
+    io::{Read, Write},
+    path::Path,
+};
+
+use ftp::{
+    mock::{scratch_file, test_users, MockFtpServer},
+    Config, RateLimits,
+};
+
+/// A download slow enough to still be running when the test interrupts it
+fn slow_server(audit_log: &Path) -> MockFtpServer {
+    MockFtpServer::with_config(
+        Config::new(test_users())
+            .rate_limits(RateLimits {
+                per_session: Some(64 * 1024),
+                ..RateLimits::default()
+            })
+            .audit_log(audit_log),
+    )
+}
+
+#[test]
+fn abor_interrupts_a_download() {
+    let path = scratch_file("abort");
+    fs::write(&path, vec![b'x'; 1024 * 1024]).unwrap();
+
+    let log = scratch_file("abort.json");
+    let mut server = slow_server(&log);
+    server.send_bytes(b"TYPE I\r\n");
+    server.assert_output(b"200 Type is now 8-bit binary.\r\n");
+    let mut data = server.pasv();
+
+    server.send_bytes(format!("RETR {}\r\n", path.display()).as_bytes());
+    server.assert_output(b"150 Connecting to data port.\r\n");
+
+    let mut buffer = [0; 1024];
+    data.read_exact(&mut buffer).unwrap();
+
+    server.send_bytes(b"STAT\r\n");
+    let progress = server.read_line();
+    assert!(progress.starts_with("213 Transferred "), "{:?}", progress);
+    assert!(
+        progress.ends_with(" of 1048576 bytes.\r\n"),
+        "{:?}",
+        progress
+    );
+
+    // Telnet Interrupt Process and Synch, as sent by most clients
+    server.send_bytes(b"\xff\xf4\xff\xf2ABOR\r\n");
+    server.assert_output(b"426 Connection closed; transfer aborted.\r\n");
+    server.assert_output(b"226 Abort successful.\r\n");
+
+    let mut rest = Vec::new();
+    data.read_to_end(&mut rest).unwrap();
+    assert!(rest.len() < 1024 * 1024);
+
+    server.send_bytes(b"NOOP\r\n");
+    server.assert_output(b"200 NOOP\r\n");
+
+    // commands answered during the transfer are audited like any other
+    let audited: Vec<String> = fs::read_to_string(&log)
+        .unwrap()
+        .lines()
+        .map(|line| {
+            let field = |name: &str| {
+                let key = format!("\"{}\":", name);
+                let value = &line[line.find(&key).unwrap() + key.len()..];
+                value
+                    .split(',')
+                    .next()
+                    .unwrap()
+                    .trim_matches('"')
+                    .to_owned()
+            };
+            format!("{} {}", field("verb"), field("reply"))
+        })
+        .collect();
+    // after USER, PASS, TYPE and PASV, and before the NOOP which may not be
+    // recorded yet
+    assert_eq!(audited[4..7], ["STAT 213", "RETR 426", "ABOR 226"]);
+}
+
+#[test]
+fn commands_wait_for_the_transfer_and_abor_without_one() {
+    let path = scratch_file("abort-queued");
+
+    let mut server = MockFtpServer::new();
+    let mut data = server.pasv();
+
+    server.send_bytes(format!("STOR {}\r\n", path.display()).as_bytes());
+    server.assert_output(b"150 Connecting to data port.\r\n");
+
+    server.send_bytes(b"NOOP\r\n");
+    data.write_all(b"hello").unwrap();
+    drop(data);
+
+    server.assert_output(b"226 Closing connection\r\n");
+    server.assert_output(b"200 NOOP\r\n");
+    assert_eq!(fs::read(&path).unwrap(), b"hello");
+
+    server.send_bytes(b"ABOR\r\n");
+    server.assert_output(b"226 No transfer to abort.\r\n");
+}
@@ -0,0 +1,50 @@
+use std::{collections::BTreeMap, net::IpAddr};
+
+use ftp::{
+    mock::{test_users, MockFtpServer},
+    AccessRules, Cidr, Config, User,
+};
+
+fn cidr(s: &str) -> Cidr {
+    s.parse().unwrap()
+}
+
+fn ip(s: &str) -> IpAddr {
+    s.parse().unwrap()
+}
+
+#[test]
+fn cidr_matches_ipv4_and_ipv6() {
+    assert!(cidr("10.0.0.0/8").contains(ip("10.1.2.3")));
+    assert!(!cidr("10.0.0.0/8").contains(ip("11.0.0.1")));
+    assert!(cidr("10.0.0.0/8").contains(ip("::ffff:10.0.0.1")));
+    assert!(cidr("2001:db8::/32").contains(ip("2001:db8:1::1")));
+    assert!(!cidr("2001:db8::/32").contains(ip("2001:db9::1")));
+    assert!(cidr("0.0.0.0/0").contains(ip("192.168.1.1")));
+    assert!(cidr("127.0.0.1").contains(ip("127.0.0.1")));
+    assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+}
+
+#[test]
+fn global_deny_refuses_connection() {
+    let mut server = MockFtpServer::start(
+        Config::new(test_users()).access(AccessRules::default().deny(cidr("127.0.0.0/8"))),
+    );
+    server.assert_output(b"421 Access denied from your address.\r\n");
+    server.assert_closed();
+}
+
+#[test]
+fn per_user_rules_checked_at_pass() {
+    let mut users = BTreeMap::new();
+    users.insert(
+        "a".to_owned(),
+        User::new("a").access(AccessRules::default().allow(cidr("10.0.0.0/8"))),
+    );
+
+    let mut server = MockFtpServer::unauthenticated(Config::new(users));
+    server.send_bytes(b"USER a\r\n");
+    server.assert_output(b"331 Username Ok. Password needed.\r\n");
+    server.send_bytes(b"PASS a\r\n");
+    server.assert_output(b"530 Login not permitted from your address.\r\n");
+}
@@ -0,0 +1,53 @@
+use ftp::{
+    mock::{test_users, MockFtpServer},
+    AccountRequirement, Config, User,
+};
+
+fn server_with_account(required: AccountRequirement) -> MockFtpServer {
+    let mut users = test_users();
+    users.insert("c".to_owned(), User::new("c").account("billing", required));
+
+    let mut server = MockFtpServer::unauthenticated(Config::new(users));
+    server.send_bytes(b"USER c\r\n");
+    server.assert_output(b"331 Username Ok. Password needed.\r\n");
+    server
+}
+
+#[test]
+fn account_needed_to_log_in() {
+    let mut server = server_with_account(AccountRequirement::Login);
+
+    server.send_bytes(b"ACCT billing\r\n");
+    server.assert_output(b"503 Expected `PASS`.\r\n");
+
+    server.send_bytes(b"PASS c\r\n");
+    server.assert_output(b"332 Need account for login.\r\n");
+
+    server.send_bytes(b"ACCT wrong\r\n");
+    server.assert_output(b"530 Incorrect account.\r\n");
+
+    server.send_bytes(b"PASS c\r\n");
+    server.assert_output(b"332 Need account for login.\r\n");
+
+    server.send_bytes(b"ACCT billing\r\n");
+    server.assert_output(b"230 Logged in.\r\n");
+    assert_eq!(server.handle().sessions_for("c"), 1);
+}
+
+#[test]
+fn account_needed_to_store() {
+    let mut server = server_with_account(AccountRequirement::Store);
+
+    server.send_bytes(b"PASS c\r\n");
+    server.assert_output(b"230 Logged in.\r\n");
+
+    server.send_bytes(b"STOR never-written\r\n");
+    server.assert_output(b"532 Need account for storing files.\r\n");
+
+    server.send_bytes(b"ACCT billing\r\n");
+    server.assert_output(b"230 Account accepted.\r\n");
+
+    let mut other = MockFtpServer::new();
+    other.send_bytes(b"ACCT anything\r\n");
+    other.assert_output(b"202 Account not needed.\r\n");
+}
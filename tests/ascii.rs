@@ -0,0 +1,62 @@
+use std::fs;
+
+use ftp::mock::{scratch_file, MockFtpServer};
+
+#[test]
+fn ascii_converts_line_endings_both_ways() {
+    let path = scratch_file("ascii-lines");
+    let path_str = path.to_str().unwrap();
+
+    let mut server = MockFtpServer::new();
+
+    // CR NUL is a bare CR, and a CR before anything else is kept
+    server.stor(path_str, b"one\r\ntwo\r\0three\rfour\r\n");
+    assert_eq!(fs::read(&path).unwrap(), b"one\ntwo\rthree\rfour\n");
+
+    fs::write(&path, b"one\ntwo\rthree\n").unwrap();
+    assert_eq!(server.retr(path_str), b"one\r\ntwo\r\0three\r\n");
+
+    // no line ending is added to a file without one
+    fs::write(&path, b"no newline").unwrap();
+    assert_eq!(server.retr(path_str), b"no newline");
+}
+
+#[test]
+fn ascii_round_trips_across_buffer_boundaries() {
+    let path = scratch_file("ascii-large");
+    let path_str = path.to_str().unwrap();
+
+    let local: Vec<u8> = (0..200_000u32)
+        .flat_map(|i| match i % 7 {
+            0 => b"\n".to_vec(),
+            1 => b"\r".to_vec(),
+            _ => vec![b'a' + (i % 26) as u8],
+        })
+        .collect();
+    fs::write(&path, &local).unwrap();
+
+    let mut server = MockFtpServer::new();
+    let wire = server.retr(path_str);
+    assert_eq!(
+        wire.len(),
+        local.len() + local.iter().filter(|&&b| b == b'\n' || b == b'\r').count()
+    );
+
+    server.stor(path_str, &wire);
+    assert_eq!(fs::read(&path).unwrap(), local);
+}
+
+#[test]
+fn image_is_byte_for_byte() {
+    let path = scratch_file("ascii-image");
+    let path_str = path.to_str().unwrap();
+    let data = b"\r\n\n\r\0\xff no trailing newline";
+
+    let mut server = MockFtpServer::new();
+    server.send_bytes(b"TYPE I\r\n");
+    server.assert_output(b"200 Type is now 8-bit binary.\r\n");
+
+    server.stor(path_str, data);
+    assert_eq!(fs::read(&path).unwrap(), data);
+    assert_eq!(server.retr(path_str), data);
+}
@@ -0,0 +1,70 @@
+use std::{
+    fs,
+    net::SocketAddr,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use ftp::{
+    mock::{scratch_dir, test_users, MockFtpServer},
+    AuditRecord, Code, Config,
+};
+
+#[test]
+fn records_every_command_with_masked_passwords() {
+    let dir = scratch_dir("audit");
+
+    let log = dir.join("audit.json");
+
+    let mut server = MockFtpServer::with_config(Config::new(test_users()).audit_log(&log));
+
+    server.send_bytes(b"PWD\r\n");
+    server.assert_output(b"200 .\r\n");
+    server.send_bytes(b"QUIT\r\n");
+    server.assert_output(b"221 Goodbye!\r\n");
+    server.assert_closed();
+
+    let log = fs::read_to_string(&log).unwrap();
+    let lines: Vec<&str> = log.lines().collect();
+
+    let expected = [
+        r#""user":"a","verb":"USER","argument":"a","reply":331"#,
+        r#""user":"a","verb":"PASS","argument":"****","reply":230"#,
+        r#""user":"a","verb":"PWD","argument":"","reply":200"#,
+        r#""user":"a","verb":"QUIT","argument":"","reply":221"#,
+    ];
+
+    assert_eq!(lines.len(), expected.len(), "{}", log);
+
+    for (line, expected) in lines.iter().zip(expected.iter()) {
+        assert!(line.starts_with(r#"{"time":""#), "{}", line);
+        assert!(line.contains(r#","session":"#), "{}", line);
+        assert!(line.contains(r#","remote":"127.0.0.1:"#), "{}", line);
+        assert!(
+            line.contains(expected),
+            "{} does not contain {}",
+            line,
+            expected
+        );
+        assert!(line.contains(r#","latency_us":"#), "{}", line);
+        assert!(line.ends_with('}'), "{}", line);
+    }
+}
+
+#[test]
+fn escapes_json_strings() {
+    let record = AuditRecord {
+        time: UNIX_EPOCH + Duration::from_millis(1_500),
+        session: 7,
+        remote: "[::1]:2121".parse::<SocketAddr>().unwrap(),
+        user: None,
+        verb: "CWD",
+        argument: "a \"quoted\" \\ dir\u{1}",
+        reply: Some(Code::RequestedFileActionComplete),
+        latency: Duration::from_micros(42),
+    };
+
+    assert_eq!(
+        record.to_json(),
+        r#"{"time":"1970-01-01T00:00:01.500Z","session":7,"remote":"[::1]:2121","user":null,"verb":"CWD","argument":"a \"quoted\" \\ dir\u0001","reply":250,"latency_us":42}"#
+    );
+}
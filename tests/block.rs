@@ -0,0 +1,81 @@
+use std::{fs, io::Write};
+
+use ftp::{
+    mock::{scratch_file, test_users, MockFtpServer},
+    Config,
+};
+
+fn block_mode(config: Config) -> MockFtpServer {
+    let mut server = MockFtpServer::with_config(config);
+    server.send_bytes(b"TYPE I\r\n");
+    server.read_line();
+    server.send_bytes(b"MODE B\r\n");
+    server.assert_output(b"200 Transfer mode is now block.\r\n");
+    server
+}
+
+#[test]
+fn downloads_resume_from_restart_markers() {
+    let path = scratch_file("block-download");
+    let path_str = path.to_str().unwrap();
+    fs::write(&path, b"abcdefghij").unwrap();
+
+    let mut server = MockFtpServer::new();
+    server.send_bytes(b"REST 4\r\n");
+    server.assert_output(b"504 Restart markers are only sent in block and compressed mode.\r\n");
+
+    let mut server = block_mode(Config::new(test_users()).restart_marker_interval(4));
+
+    assert_eq!(
+        server.retr(path_str),
+        b"\x00\x00\x04abcd\x10\x00\x014\x00\x00\x04efgh\x10\x00\x018\x40\x00\x02ij"
+    );
+
+    server.send_bytes(b"REST 8\r\n");
+    server.assert_output(b"350 Restarting at 8. Send RETR or STOR to resume.\r\n");
+    assert_eq!(server.retr(path_str), b"\x40\x00\x02ij");
+}
+
+#[test]
+fn uploads_report_marks_and_resume() {
+    let path = scratch_file("block-upload");
+    let path_str = path.to_str().unwrap();
+
+    let mut server = block_mode(Config::new(test_users()));
+
+    // the connection closes before the block with EOF
+    let mut data = server.pasv();
+    server.send_bytes(format!("STOR {}\r\n", path_str).as_bytes());
+    server.assert_output(b"150 Connecting to data port.\r\n");
+    data.write_all(b"\x00\x00\x03abc\x10\x00\x02r1\x00\x00\x02de")
+        .unwrap();
+    drop(data);
+    server.assert_output(b"110 MARK r1 = 3\r\n");
+    server.assert_output(
+        b"451 Invalid data received: data connection closed before the end of the file.\r\n",
+    );
+
+    server.send_bytes(b"REST 3\r\n");
+    server.read_line();
+    server.stor(path_str, b"\x40\x00\x02DE");
+    assert_eq!(fs::read(&path).unwrap(), b"abcDE");
+}
+
+#[test]
+fn records_end_with_eor_flags() {
+    let path = scratch_file("block-records");
+    let path_str = path.to_str().unwrap();
+    fs::write(&path, b"a\n\xffb").unwrap();
+
+    let mut server = block_mode(Config::new(test_users()));
+    server.send_bytes(b"TYPE A\r\n");
+    server.read_line();
+    server.send_bytes(b"STRU R\r\n");
+    server.read_line();
+
+    let wire = server.retr(path_str);
+    assert_eq!(wire, b"\x80\x00\x01a\xc0\x00\x02\xffb");
+
+    server.stor(path_str, &wire);
+    assert_eq!(fs::read(&path).unwrap(), b"a\n\xffb\n");
+}
@@ -0,0 +1,74 @@
+use std::{fs, io::Write};
+
+use ftp::mock::{scratch_file, MockFtpServer};
+
+fn compressed_mode(data_type: &str) -> MockFtpServer {
+    let mut server = MockFtpServer::new();
+    server.send_bytes(format!("TYPE {}\r\n", data_type).as_bytes());
+    server.read_line();
+    server.send_bytes(b"MODE C\r\n");
+    server.assert_output(b"200 Transfer mode is now compressed.\r\n");
+    server
+}
+
+#[test]
+fn runs_are_replicated_or_filled() {
+    let path = scratch_file("compressed-runs");
+    let path_str = path.to_str().unwrap();
+
+    let mut server = compressed_mode("I");
+
+    fs::write(&path, b"abc\0\0\0\0\0\0\0\0\0\0xxxxxd").unwrap();
+    assert_eq!(server.retr(path_str), b"\x03abc\xca\x85x\x01d\x00\x40");
+
+    // literal strings hold at most 127 bytes, and runs at most 63
+    let local: Vec<u8> = (0..200_000u32)
+        .map(|i| match i % 1000 {
+            0..=99 => 0,
+            100..=299 => b'r',
+            n => (n * 7) as u8,
+        })
+        .collect();
+    fs::write(&path, &local).unwrap();
+    let wire = server.retr(path_str);
+    assert!(wire.len() < local.len());
+
+    server.stor(path_str, &wire);
+    assert_eq!(fs::read(&path).unwrap(), local);
+}
+
+#[test]
+fn records_are_compressed_with_spaces_as_filler() {
+    let path = scratch_file("compressed-records");
+    let path_str = path.to_str().unwrap();
+
+    let mut server = compressed_mode("A");
+    server.send_bytes(b"STRU R\r\n");
+    server.read_line();
+
+    fs::write(&path, b"ab    c\n").unwrap();
+    let wire = server.retr(path_str);
+    assert_eq!(wire, b"\x02ab\xc4\x01c\x00\x80\x00\x40");
+
+    server.stor(path_str, &wire);
+    assert_eq!(fs::read(&path).unwrap(), b"ab    c\n");
+}
+
+#[test]
+fn uploads_report_restart_markers() {
+    let path = scratch_file("compressed-marker");
+    let path_str = path.to_str().unwrap();
+
+    let mut server = compressed_mode("I");
+
+    let mut data = server.pasv();
+    server.send_bytes(format!("STOR {}\r\n", path_str).as_bytes());
+    server.assert_output(b"150 Connecting to data port.\r\n");
+    data.write_all(b"\x03abc\x00\x10\x02m1\x82z\x00\x40")
+        .unwrap();
+    drop(data);
+    server.assert_output(b"110 MARK m1 = 3\r\n");
+    server.assert_output(b"226 Closing connection\r\n");
+
+    assert_eq!(fs::read(&path).unwrap(), b"abczz");
+}
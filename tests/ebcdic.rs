@@ -0,0 +1,63 @@
+use std::fs;
+
+use ftp::{
+    mock::{scratch_file, test_users, MockFtpServer},
+    CodePage, Config,
+};
+
+fn type_e(server: &mut MockFtpServer) {
+    server.send_bytes(b"TYPE E\r\n");
+    server.assert_output(b"200 Type is now EBCDIC.\r\n");
+}
+
+#[test]
+fn translates_text_and_line_endings() {
+    let path = scratch_file("ebcdic-text");
+    let path_str = path.to_str().unwrap();
+
+    let mut server = MockFtpServer::new();
+    type_e(&mut server);
+
+    // "Hello [1]" NL in code page 037
+    let ebcdic = b"\xc8\x85\x93\x93\x96\x40\xba\xf1\xbb\x15";
+    server.stor(path_str, ebcdic);
+    assert_eq!(fs::read(&path).unwrap(), b"Hello [1]\n");
+    assert_eq!(server.retr(path_str), ebcdic);
+
+    server.send_bytes(b"SITE CODEPAGE\r\n");
+    server.assert_output(b"200 EBCDIC code page is 037.\r\n");
+    server.send_bytes(b"SITE CODEPAGE IBM-1047\r\n");
+    server.assert_output(b"200 EBCDIC code page is now 1047.\r\n");
+    assert_eq!(
+        server.retr(path_str),
+        b"\xc8\x85\x93\x93\x96\x40\xad\xf1\xbd\x15"
+    );
+
+    server.send_bytes(b"SITE CODEPAGE 500\r\n");
+    server.assert_output(b"501 Unknown EBCDIC code page \"500\"; 037 and 1047 are supported.\r\n");
+}
+
+#[test]
+fn every_byte_round_trips_in_both_code_pages() {
+    let path = scratch_file("ebcdic-all");
+    let path_str = path.to_str().unwrap();
+    let local: Vec<u8> = (0..=255).collect();
+
+    for code_page in [CodePage::Cp037, CodePage::Cp1047] {
+        fs::write(&path, &local).unwrap();
+
+        let mut server =
+            MockFtpServer::with_config(Config::new(test_users()).ebcdic_code_page(code_page));
+        type_e(&mut server);
+
+        let ebcdic = server.retr(path_str);
+        let mut sorted = ebcdic.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, local, "{:?} is not one to one", code_page);
+        assert_eq!(ebcdic[b'\n' as usize], 0x15);
+        assert_eq!(ebcdic[b'A' as usize], 0xc1);
+
+        server.stor(path_str, &ebcdic);
+        assert_eq!(fs::read(&path).unwrap(), local);
+    }
+}
@@ -0,0 +1,41 @@
+use std::fs;
+
+use ftp::mock::{scratch_file, MockFtpServer};
+
+#[test]
+fn parses_format_control() {
+    let mut server = MockFtpServer::new();
+
+    server.send_bytes(b"TYPE A T\r\n");
+    server.assert_output(b"200 Type is now ASCII with Telnet format controls.\r\n");
+    server.send_bytes(b"TYPE E C\r\n");
+    server.assert_output(b"200 Type is now EBCDIC with carriage control.\r\n");
+    server.send_bytes(b"TYPE A N\r\n");
+    server.assert_output(b"200 Type is now ASCII.\r\n");
+    server.send_bytes(b"TYPE A X\r\n");
+    server.assert_output(b"504 Unknown format control: X.\r\n");
+}
+
+#[test]
+fn carriage_control_round_trips_print_files() {
+    let path = scratch_file("format-asa");
+    let path_str = path.to_str().unwrap();
+
+    let mut server = MockFtpServer::new();
+    server.send_bytes(b"TYPE A C\r\n");
+    server.assert_output(b"200 Type is now ASCII with carriage control.\r\n");
+
+    let local = b"\x0cTitle\nline 2\n\nline 4\rover\n\n\n\n\nlast\n";
+    let wire = b"1Title\r\n line 2\r\n0line 4\r\n+over\r\n-\r\n0last\r\n";
+
+    fs::write(&path, local).unwrap();
+    assert_eq!(server.retr(path_str), wire);
+
+    fs::remove_file(&path).unwrap();
+    server.stor(path_str, wire);
+    assert_eq!(fs::read(&path).unwrap(), local);
+
+    // a line with no control character advances one line
+    server.stor(path_str, b"1Page\r\n\r\n next\r\n");
+    assert_eq!(fs::read(&path).unwrap(), b"\x0cPage\n\nnext\n");
+}
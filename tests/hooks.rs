@@ -0,0 +1,87 @@
+use std::sync::{Arc, Mutex};
+
+use ftp::{
+    mock::{scratch_dir, test_users, MockFtpServer},
+    Config, Event, Observer, Operation, SessionInfo,
+};
+
+#[derive(Default)]
+struct Recorder {
+    events: Mutex<Vec<String>>,
+}
+
+impl Observer for Recorder {
+    fn before(&self, _session: &SessionInfo<'_>, operation: &Operation<'_>) -> Result<(), String> {
+        match operation {
+            Operation::Store { path } if path.extension().is_some_and(|ext| ext == "exe") => {
+                Err("Executables are not allowed.".to_owned())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn after(&self, session: &SessionInfo<'_>, event: &Event<'_>) {
+        let event = match event {
+            Event::Connect => "connect".to_owned(),
+            Event::Login { success, .. } => format!("login {}", success),
+            Event::UploadComplete { path, bytes, .. } => {
+                format!(
+                    "upload {} {}",
+                    path.file_name().unwrap().to_string_lossy(),
+                    bytes
+                )
+            }
+            Event::Rename { to, .. } => {
+                format!("rename {}", to.file_name().unwrap().to_string_lossy())
+            }
+            Event::Delete { .. } => "delete".to_owned(),
+            Event::Disconnect => "disconnect".to_owned(),
+            event => format!("{:?}", event),
+        };
+
+        assert!(session.ip.is_loopback());
+
+        self.events.lock().unwrap().push(event);
+    }
+}
+
+#[test]
+fn observes_and_vetoes_file_events() {
+    let dir = scratch_dir("hooks");
+
+    let recorder = Arc::new(Recorder::default());
+
+    let mut server =
+        MockFtpServer::with_config(Config::new(test_users()).observer(Arc::clone(&recorder)));
+
+    let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+
+    server.send_bytes(format!("STOR {}\r\n", path("virus.exe")).as_bytes());
+    server.assert_output(b"553 Executables are not allowed.\r\n");
+
+    server.stor(&path("a.txt"), b"hello");
+
+    server.send_bytes(format!("RNFR {}\r\n", path("a.txt")).as_bytes());
+    server.assert_output(b"350 Ready for destination name.\r\n");
+    server.send_bytes(format!("RNTO {}\r\n", path("b.txt")).as_bytes());
+    assert!(server.read_line().starts_with("250 "));
+
+    server.send_bytes(format!("DELE {}\r\n", path("b.txt")).as_bytes());
+    assert!(server.read_line().starts_with("250 "));
+
+    server.send_bytes(b"QUIT\r\n");
+    server.assert_output(b"221 Goodbye!\r\n");
+    server.assert_closed();
+
+    assert_eq!(
+        *recorder.events.lock().unwrap(),
+        [
+            "connect",
+            "login true",
+            "upload a.txt 5",
+            "rename b.txt",
+            "delete",
+            "disconnect"
+        ]
+    );
+}
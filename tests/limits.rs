@@ -0,0 +1,60 @@
+use std::net::{IpAddr, Ipv4Addr};
+
+use ftp::{
+    mock::{test_users, MockFtpServer},
+    Config, Limits,
+};
+
+const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+#[test]
+fn rejects_sessions_over_global_limit() {
+    let server = MockFtpServer::unauthenticated(Config::new(test_users()).limits(Limits {
+        max_sessions: Some(1),
+        ..Limits::default()
+    }));
+
+    let mut second = server.connect();
+    second.assert_output(b"421 Too many users, try again later.\r\n");
+    second.assert_closed();
+
+    assert_eq!(server.handle().sessions(), 1);
+    assert_eq!(server.handle().sessions_from(LOCALHOST), 1);
+}
+
+#[test]
+fn rejects_sessions_over_per_ip_limit() {
+    let server = MockFtpServer::unauthenticated(Config::new(test_users()).limits(Limits {
+        max_sessions_per_ip: Some(1),
+        ..Limits::default()
+    }));
+
+    let mut second = server.connect();
+    second.assert_output(b"421 Too many connections from your address.\r\n");
+    second.assert_closed();
+}
+
+#[test]
+fn rejects_logins_over_per_user_limit() {
+    let server = MockFtpServer::with_config(Config::new(test_users()).limits(Limits {
+        max_sessions_per_user: Some(1),
+        ..Limits::default()
+    }));
+
+    assert_eq!(server.handle().sessions_for("a"), 1);
+
+    let mut second = server.connect();
+    second.assert_output(b"220 Server ready for new user.\r\n");
+    second.send_bytes(b"USER a\r\n");
+    second.assert_output(b"331 Username Ok. Password needed.\r\n");
+    second.send_bytes(b"PASS a\r\n");
+    second.assert_output(b"421 Too many sessions for this user.\r\n");
+    second.assert_closed();
+
+    let mut third = server.connect();
+    third.assert_output(b"220 Server ready for new user.\r\n");
+    third.login("b", "b");
+
+    assert_eq!(server.handle().sessions_for("a"), 1);
+    assert_eq!(server.handle().sessions_for("b"), 1);
+}
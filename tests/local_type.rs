@@ -0,0 +1,58 @@
+use std::{fs, io::Read};
+
+use ftp::mock::{scratch_file, MockFtpServer};
+
+#[test]
+fn parses_byte_sizes() {
+    let mut server = MockFtpServer::new();
+
+    server.send_bytes(b"TYPE L 36\r\n");
+    server.assert_output(b"200 Type is now local byte size 36.\r\n");
+    server.send_bytes(b"TYPE L 8\r\n");
+    server.assert_output(b"200 Type is now 8-bit binary.\r\n");
+    server.send_bytes(b"TYPE L 65\r\n");
+    server.assert_output(b"504 Byte size must be from 8 to 64.\r\n");
+    server.send_bytes(b"TYPE L 3\r\n");
+    server.assert_output(b"504 Byte size must be from 8 to 64.\r\n");
+    server.send_bytes(b"TYPE L\r\n");
+    server.assert_output(b"501 Missing byte size.\r\n");
+}
+
+#[test]
+fn packs_and_unpacks_logical_bytes() {
+    let path = scratch_file("local-type-pack");
+    let path_str = path.to_str().unwrap();
+
+    let mut server = MockFtpServer::new();
+
+    // two 36-bit words, widened to 64 bits locally, fill 9 transfer bytes
+    server.send_bytes(b"TYPE L 36\r\n");
+    server.read_line();
+    let local = [
+        0, 0, 0, 0x0f, 0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0, 0, 0, 0, 1,
+    ];
+    let wire = [0xff, 0xff, 0xff, 0xff, 0xf0, 0, 0, 0, 1];
+    fs::write(&path, local).unwrap();
+    assert_eq!(server.retr(path_str), wire);
+    server.stor(path_str, &wire);
+    assert_eq!(fs::read(&path).unwrap(), local);
+
+    // 12-bit bytes are padded out to a whole transfer byte
+    server.send_bytes(b"TYPE L 12\r\n");
+    server.read_line();
+    let local = [0x0a, 0xbc, 0x0d, 0xef, 0x01, 0x23];
+    let wire = [0xab, 0xcd, 0xef, 0x12, 0x30];
+    fs::write(&path, local).unwrap();
+    assert_eq!(server.retr(path_str), wire);
+    server.stor(path_str, &wire);
+    assert_eq!(fs::read(&path).unwrap(), local);
+
+    // a local byte which doesn't fit can't be sent
+    fs::write(&path, [0x10, 0]).unwrap();
+    let mut data = server.pasv();
+    server.send_bytes(format!("RETR {}\r\n", path_str).as_bytes());
+    server.assert_output(b"150 Connecting to data port.\r\n");
+    data.read_to_end(&mut Vec::new()).unwrap();
+    let reply = server.read_line();
+    assert!(reply.starts_with("451 "), "{:?}", reply);
+}
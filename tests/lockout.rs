@@ -0,0 +1,92 @@
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use ftp::{
+    mock::{test_users, MockFtpServer},
+    Config, LockoutPolicy,
+};
+
+const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+fn policy() -> LockoutPolicy {
+    LockoutPolicy {
+        max_attempts: 2,
+        base_delay: Duration::from_millis(0),
+        ban_after: Some(3),
+        ..LockoutPolicy::default()
+    }
+}
+
+fn fail_login(server: &mut MockFtpServer) {
+    server.send_bytes(b"USER a\r\n");
+    server.assert_output(b"331 Username Ok. Password needed.\r\n");
+    server.send_bytes(b"PASS wrong\r\n");
+    server.assert_output(b"530 Incorrect password.\r\n");
+}
+
+#[test]
+fn disconnects_after_max_attempts() {
+    let mut server = MockFtpServer::unauthenticated(Config::new(test_users()).lockout(policy()));
+
+    fail_login(&mut server);
+    fail_login(&mut server);
+    server.assert_output(b"421 Too many failed logins.\r\n");
+    server.assert_closed();
+}
+
+#[test]
+fn bans_and_unbans_ip() {
+    let mut server = MockFtpServer::unauthenticated(Config::new(test_users()).lockout(policy()));
+
+    fail_login(&mut server);
+    fail_login(&mut server);
+    server.assert_output(b"421 Too many failed logins.\r\n");
+
+    let mut second = server.connect();
+    second.assert_output(b"220 Server ready for new user.\r\n");
+    fail_login(&mut second);
+    second.assert_output(b"421 Too many failed logins, your address is temporarily banned.\r\n");
+    second.assert_closed();
+
+    let bans = server.handle().bans();
+    assert_eq!(bans.len(), 1);
+    assert_eq!(bans[0].ip, LOCALHOST);
+
+    let mut third = server.connect();
+    third.assert_output(b"421 Your address is temporarily banned.\r\n");
+    third.assert_closed();
+
+    server.handle().clear_bans();
+    assert!(server.handle().bans().is_empty());
+
+    let mut fourth = server.connect();
+    fourth.assert_output(b"220 Server ready for new user.\r\n");
+    fourth.login("a", "a");
+}
+
+#[test]
+fn reports_attempts_to_hook() {
+    let attempts = Arc::new(Mutex::new(Vec::new()));
+    let recorded = Arc::clone(&attempts);
+
+    let mut server =
+        MockFtpServer::unauthenticated(Config::new(test_users()).lockout(policy()).login_hook(
+            move |attempt| {
+                recorded
+                    .lock()
+                    .unwrap()
+                    .push((attempt.username.to_owned(), attempt.success))
+            },
+        ));
+
+    fail_login(&mut server);
+    server.login("a", "a");
+
+    assert_eq!(
+        *attempts.lock().unwrap(),
+        vec![("a".to_owned(), false), ("a".to_owned(), true)]
+    );
+}
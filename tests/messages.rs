@@ -0,0 +1,43 @@
+use std::fs;
+
+use ftp::{
+    mock::{scratch_dir, test_users, MockFtpServer},
+    Config,
+};
+
+#[test]
+fn sends_banner_from_file_and_login_message() {
+    let dir = scratch_dir("banner");
+
+    let banner = dir.join("banner.txt");
+    fs::write(&banner, "Welcome to\r\n200 servers\r\nEnjoy\r\n\r\n").unwrap();
+
+    let mut server = MockFtpServer::start(
+        Config::new(test_users())
+            .banner_file(&banner)
+            .login_message("Hello %U, 100%% of %N sessions."),
+    );
+
+    server.assert_output(b"220-Welcome to\r\n  200 servers\r\n220 Enjoy\r\n");
+
+    server.send_bytes(b"USER a\r\n");
+    server.assert_output(b"331 Username Ok. Password needed.\r\n");
+    server.send_bytes(b"PASS a\r\n");
+    server.assert_output(b"230-Hello a, 100% of 1 sessions.\r\n230 Logged in.\r\n");
+}
+
+#[test]
+fn sends_directory_messages_on_cwd() {
+    let dir = scratch_dir("dot-message");
+    fs::write(dir.join(".message"), "Uploads go here, %U.\n").unwrap();
+
+    let mut server = MockFtpServer::new();
+
+    server.send_bytes(format!("CWD {}\r\n", dir.display()).as_bytes());
+    server.assert_output(b"250-Uploads go here, a.\r\n250 Changed directory.\r\n");
+
+    let mut quiet = MockFtpServer::with_config(Config::new(test_users()).directory_messages(false));
+
+    quiet.send_bytes(format!("CWD {}\r\n", dir.display()).as_bytes());
+    quiet.assert_output(b"250 Changed directory.\r\n");
+}
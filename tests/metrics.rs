@@ -0,0 +1,61 @@
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+};
+
+use ftp::mock::{scratch_dir, MockFtpServer};
+
+fn get(addr: std::net::SocketAddr, path: &str) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    response
+}
+
+#[test]
+fn serves_prometheus_metrics() {
+    let dir = scratch_dir("metrics");
+    let file = dir.join("file.txt");
+    let file = file.to_str().unwrap();
+
+    let mut server = MockFtpServer::new();
+    let addr = server.handle().serve_metrics("127.0.0.1:0").unwrap();
+
+    server.stor(file, b"hello");
+    server.retr(file);
+    server.send_bytes(b"XYZW\r\n");
+    server.assert_output(b"500 Command not recognized.\r\n");
+
+    // each command is counted after its reply is sent
+    server.send_bytes(b"NOOP\r\n");
+    server.assert_output(b"200 NOOP\r\n");
+
+    let response = get(addr, "/metrics");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+
+    for line in &[
+        "ftp_sessions_active 1",
+        "ftp_logins_total{result=\"success\"} 1",
+        "ftp_commands_total{verb=\"PASS\",class=\"2xx\"} 1",
+        "ftp_commands_total{verb=\"USER\",class=\"3xx\"} 1",
+        "ftp_commands_total{verb=\"STOR\",class=\"2xx\"} 1",
+        "ftp_commands_total{verb=\"unknown\",class=\"5xx\"} 1",
+        "ftp_transfer_bytes_total{direction=\"download\"} 5",
+        "ftp_transfer_bytes_total{direction=\"upload\"} 5",
+        "ftp_transfer_duration_seconds_count{direction=\"upload\"} 1",
+        "ftp_transfer_duration_seconds_bucket{direction=\"download\",le=\"+Inf\"} 1",
+        "ftp_data_connection_failures_total 0",
+    ] {
+        assert!(
+            response.lines().any(|l| l == *line),
+            "missing {:?} in\n{}",
+            line,
+            response
+        );
+    }
+
+    assert!(get(addr, "/").starts_with("HTTP/1.1 404 Not Found\r\n"));
+}
@@ -0,0 +1,82 @@
+use ftp::{
+    mock::{test_users, MockFtpServer},
+    Config,
+};
+
+#[test]
+fn cdup_stays_within_the_root() {
+    let mut server = MockFtpServer::new();
+
+    server.send_bytes(b"CDUP\r\n");
+    server.assert_output(b"550 Already at the top directory.\r\n");
+
+    server.send_bytes(b"CWD src\r\n");
+    server.assert_output(b"250 Changed directory.\r\n");
+
+    server.send_bytes(b"XCUP\r\n");
+    server.assert_output(b"200 Changed directory.\r\n");
+
+    server.send_bytes(b"PWD\r\n");
+    server.assert_output(b"200 .\r\n");
+
+    server.send_bytes(b"CDUP\r\n");
+    server.assert_output(b"550 Already at the top directory.\r\n");
+}
+
+#[test]
+fn smnt_switches_mount_points() {
+    let mut server = MockFtpServer::with_config(Config::new(test_users()).mount("tests", "tests"));
+
+    server.send_bytes(b"SMNT elsewhere\r\n");
+    server.assert_output(b"550 No such mount point \"elsewhere\".\r\n");
+
+    server.send_bytes(b"SMNT tests\r\n");
+    server.assert_output(b"250 Mounted tests.\r\n");
+
+    server.send_bytes(b"PWD\r\n");
+    server.assert_output(b"200 ./tests\r\n");
+
+    server.send_bytes(b"CDUP\r\n");
+    server.assert_output(b"550 Already at the top directory.\r\n");
+}
+
+#[test]
+fn rein_resets_the_session() {
+    let mut server = MockFtpServer::start(Config::new(test_users()).banner("Welcome."));
+    server.assert_output(b"220 Welcome.\r\n");
+    server.login("a", "a");
+
+    server.send_bytes(b"TYPE I\r\n");
+    server.read_line();
+    server.send_bytes(b"CWD src\r\n");
+    server.read_line();
+
+    server.send_bytes(b"REIN\r\n");
+    server.assert_output(b"220 Welcome.\r\n");
+    assert_eq!(server.handle().sessions_for("a"), 0);
+
+    server.send_bytes(b"PWD\r\n");
+    server.assert_output(b"200 .\r\n");
+
+    server.send_bytes(b"SMNT tests\r\n");
+    server.assert_output(b"530 Not logged in.\r\n");
+
+    server.login("b", "b");
+    assert_eq!(server.handle().sessions_for("b"), 1);
+
+    server.send_bytes(b"STAT\r\n");
+    let mut status = Vec::new();
+    loop {
+        let line = server.read_line();
+        let done = line.starts_with("211 ");
+        status.push(line);
+        if done {
+            break;
+        }
+    }
+    assert!(
+        status.iter().any(|line| line.contains("TYPE: ASCII")),
+        "{:?}",
+        status
+    );
+}
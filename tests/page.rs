@@ -0,0 +1,94 @@
+use std::{
+    fs,
+    io::{Seek, SeekFrom, Write},
+};
+
+use ftp::mock::{scratch_file, MockFtpServer};
+
+fn page_structure() -> MockFtpServer {
+    let mut server = MockFtpServer::new();
+    server.send_bytes(b"TYPE I\r\n");
+    server.read_line();
+    server.send_bytes(b"STRU P\r\n");
+    server.assert_output(b"200 Structure is now page.\r\n");
+    server
+}
+
+#[test]
+fn decodes_and_encodes_page_headers() {
+    let path = scratch_file("page-headers");
+    let path_str = path.to_str().unwrap();
+
+    let mut server = page_structure();
+
+    // a descriptor, page 0, then page 3 with access control and the end
+    server.stor(
+        path_str,
+        b"\x04\x00\x04\x02desc\x04\x00\x03\x01abc\x05\x03\x02\x03\x7fxy\x04\x04\x00\x00",
+    );
+
+    let mut expected = b"abc".to_vec();
+    expected.resize(765, 0);
+    expected.extend_from_slice(b"xy");
+    assert_eq!(fs::read(&path).unwrap(), expected);
+
+    let mut wire = Vec::new();
+    for (index, page) in expected.chunks(255).enumerate() {
+        wire.extend_from_slice(&[4, index as u8, page.len() as u8, 1]);
+        wire.extend_from_slice(page);
+    }
+    wire.extend_from_slice(&[4, 4, 0, 0]);
+    assert_eq!(server.retr(path_str), wire);
+
+    // text types would change the pages
+    server.send_bytes(b"TYPE A\r\n");
+    server.read_line();
+    server.send_bytes(format!("RETR {}\r\n", path_str).as_bytes());
+    server.assert_output(b"504 Page structure requires TYPE I, not ASCII.\r\n");
+}
+
+#[test]
+fn unknown_page_type_is_refused() {
+    let path = scratch_file("page-unknown");
+    let path_str = path.to_str().unwrap();
+
+    let mut server = page_structure();
+
+    let mut data = server.pasv();
+    server.send_bytes(format!("STOR {}\r\n", path_str).as_bytes());
+    server.assert_output(b"150 Connecting to data port.\r\n");
+    data.write_all(b"\x04\x00\x01\x09z").unwrap();
+    drop(data);
+    server.assert_output(b"551 Page type 9 unknown.\r\n");
+}
+
+#[test]
+fn holes_in_sparse_files_are_kept() {
+    let path = scratch_file("page-sparse");
+    let copy = scratch_file("page-sparse-copy");
+    let len = 16 * 1024 * 1024;
+
+    let mut file = fs::File::create(&path).unwrap();
+    file.set_len(len).unwrap();
+    file.write_all(b"start").unwrap();
+    file.seek(SeekFrom::Start(len / 2)).unwrap();
+    file.write_all(b"middle").unwrap();
+    drop(file);
+
+    let mut server = page_structure();
+
+    let wire = server.retr(path.to_str().unwrap());
+    assert!(wire.len() < len as usize / 16, "{} bytes sent", wire.len());
+
+    server.stor(copy.to_str().unwrap(), &wire);
+
+    assert_eq!(fs::read(&copy).unwrap(), fs::read(&path).unwrap());
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        let blocks = fs::metadata(&copy).unwrap().blocks();
+        assert!(blocks * 512 < len / 16, "{} blocks allocated", blocks);
+    }
+}
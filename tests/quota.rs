@@ -0,0 +1,153 @@
+use std::{collections::BTreeMap, fs, io::Write};
+
+use ftp::{
+    mock::{scratch_dir, scratch_file, test_users, MockFtpServer},
+    Config, Quota, User,
+};
+
+#[test]
+fn enforces_quota_with_552() {
+    let home = scratch_dir("quota");
+
+    let mut users = BTreeMap::new();
+    users.insert(
+        "a".to_owned(),
+        User::new("a").home(home.to_path_buf()).quota(Quota {
+            max_bytes: Some(1000),
+            max_files: Some(2),
+        }),
+    );
+
+    let mut server = MockFtpServer::with_config(Config::new(users));
+
+    server.send_bytes(b"SITE QUOTA\r\n");
+    server.assert_output(b"200 0 of 1000 bytes, 0 of 2 files used.\r\n");
+
+    server.send_bytes(b"ALLO 2000\r\n");
+    server.assert_output(b"552 Only 1000 bytes of storage remain.\r\n");
+
+    server.stor("small", &[0; 600]);
+
+    let mut data_connection = server.pasv();
+    server.send_bytes(b"STOR large\r\n");
+    server.assert_output(b"150 Connecting to data port.\r\n");
+    let _ = data_connection.write_all(&[0; 600]);
+    drop(data_connection);
+    server.assert_output(b"552 Exceeded storage allocation.\r\n");
+    assert!(!home.join("large").exists());
+
+    server.stor("other", &[0; 100]);
+
+    server.send_bytes(b"STOR third\r\n");
+    server.assert_output(b"552 File count quota exceeded.\r\n");
+
+    server.send_bytes(b"SITE QUOTA\r\n");
+    server.assert_output(b"200 700 of 1000 bytes, 2 of 2 files used.\r\n");
+
+    server.quit();
+}
+
+#[test]
+fn overwriting_past_the_quota_keeps_the_original() {
+    let home = scratch_dir("quota-overwrite");
+
+    let mut users = BTreeMap::new();
+    users.insert(
+        "a".to_owned(),
+        User::new("a").home(home.to_path_buf()).quota(Quota {
+            max_bytes: Some(1000),
+            max_files: None,
+        }),
+    );
+
+    let mut server = MockFtpServer::with_config(Config::new(users));
+
+    server.stor("kept", &[1; 600]);
+
+    let mut data_connection = server.pasv();
+    server.send_bytes(b"STOR kept\r\n");
+    server.assert_output(b"150 Connecting to data port.\r\n");
+    let _ = data_connection.write_all(&[2; 1200]);
+    drop(data_connection);
+    server.assert_output(b"552 Exceeded storage allocation.\r\n");
+
+    assert_eq!(fs::read(home.join("kept")).unwrap(), [1; 600]);
+    assert_eq!(fs::read_dir(&home).unwrap().count(), 1);
+
+    server.quit();
+}
+
+#[test]
+fn user_after_login_needs_a_password() {
+    let mut server = MockFtpServer::with_config(Config::new(test_users()));
+
+    server.send_bytes(b"USER b\r\n");
+    server.assert_output(b"331 Username Ok. Password needed.\r\n");
+    assert_eq!(server.handle().sessions_for("a"), 0);
+
+    server.send_bytes(b"SMNT tests\r\n");
+    server.assert_output(b"530 Not logged in.\r\n");
+}
+
+#[test]
+fn holes_count_against_the_quota() {
+    let home = scratch_dir("quota-holes");
+
+    let mut users = BTreeMap::new();
+    users.insert(
+        "a".to_owned(),
+        User::new("a").home(home.to_path_buf()).quota(Quota {
+            max_bytes: Some(1000),
+            max_files: None,
+        }),
+    );
+
+    let mut server = MockFtpServer::with_config(Config::new(users));
+    server.send_bytes(b"TYPE I\r\n");
+    server.read_line();
+    server.send_bytes(b"STRU P\r\n");
+    server.read_line();
+
+    // page 10 starts 2550 bytes in, leaving a hole before it
+    let mut data_connection = server.pasv();
+    server.send_bytes(b"STOR sparse\r\n");
+    server.assert_output(b"150 Connecting to data port.\r\n");
+    let _ = data_connection.write_all(b"\x04\x00\x03\x01abc\x04\x0a\x02\x01xy\x04\x0b\x00\x00");
+    drop(data_connection);
+    server.assert_output(b"552 Exceeded storage allocation.\r\n");
+    assert!(!home.join("sparse").exists());
+
+    server.quit();
+}
+
+#[test]
+fn overwriting_outside_the_home_keeps_the_quota() {
+    let home = scratch_dir("quota-outside");
+    let outside = scratch_file("quota-outside.bin");
+    fs::write(&outside, [0; 5000]).unwrap();
+    let outside_str = outside.to_str().unwrap();
+
+    let mut users = BTreeMap::new();
+    users.insert(
+        "a".to_owned(),
+        User::new("a").home(home.to_path_buf()).quota(Quota {
+            max_bytes: Some(1000),
+            max_files: None,
+        }),
+    );
+
+    let mut server = MockFtpServer::with_config(Config::new(users));
+
+    // the file being replaced never counted, so it frees nothing
+    server.stor(outside_str, &[1; 600]);
+    assert_eq!(fs::read(&outside).unwrap(), [1; 600]);
+
+    let mut data_connection = server.pasv();
+    server.send_bytes(format!("STOR {}\r\n", outside_str).as_bytes());
+    server.assert_output(b"150 Connecting to data port.\r\n");
+    let _ = data_connection.write_all(&[2; 1200]);
+    drop(data_connection);
+    server.assert_output(b"552 Exceeded storage allocation.\r\n");
+
+    server.quit();
+}
@@ -0,0 +1,84 @@
+use std::{
+    fs,
+    io::{Read, Write},
+    net::TcpStream,
+};
+
+use ftp::mock::{scratch_file, MockFtpServer};
+
+#[test]
+fn lines_are_sent_as_records() {
+    let path = scratch_file("record-lines");
+    let path_str = path.to_str().unwrap();
+
+    let mut server = MockFtpServer::new();
+    server.send_bytes(b"STRU R\r\n");
+    server.assert_output(b"200 Structure is now record.\r\n");
+
+    fs::write(&path, b"one\n\ntwo\n").unwrap();
+    assert_eq!(
+        server.retr(path_str),
+        b"one\xff\x01\xff\x01two\xff\x01\xff\x02"
+    );
+
+    // a last line without a line ending is a record all the same
+    fs::write(&path, b"one\ntwo").unwrap();
+    assert_eq!(server.retr(path_str), b"one\xff\x01two\xff\x03");
+
+    server.stor(path_str, b"one\xff\x01two\xff\x01\xff\x02");
+    assert_eq!(fs::read(&path).unwrap(), b"one\ntwo\n");
+
+    server.send_bytes(b"TYPE E\r\n");
+    server.read_line();
+    fs::write(&path, b"A\n").unwrap();
+    assert_eq!(server.retr(path_str), b"\xc1\xff\x01\xff\x02");
+}
+
+#[test]
+fn escapes_literal_0xff() {
+    let path = scratch_file("record-escape");
+    let path_str = path.to_str().unwrap();
+    let data = b"\xff\r\n\xff\xff\n";
+
+    let mut server = MockFtpServer::new();
+    server.send_bytes(b"TYPE I\r\n");
+    server.read_line();
+    server.send_bytes(b"STRU R\r\n");
+    server.read_line();
+
+    fs::write(&path, data).unwrap();
+    let wire = server.retr(path_str);
+    assert_eq!(wire, b"\xff\xff\r\xff\x01\xff\xff\xff\xff\xff\x01\xff\x02");
+
+    server.stor(path_str, &wire);
+    assert_eq!(fs::read(&path).unwrap(), data);
+}
+
+#[test]
+fn unknown_control_codes_are_refused() {
+    let path = scratch_file("record-unknown");
+    let path_str = path.to_str().unwrap();
+
+    let mut server = MockFtpServer::new();
+    let metrics = server.handle().serve_metrics("127.0.0.1:0").unwrap();
+    server.send_bytes(b"STRU R\r\n");
+    server.read_line();
+
+    let mut data = server.pasv();
+    server.send_bytes(format!("STOR {}\r\n", path_str).as_bytes());
+    server.assert_output(b"150 Connecting to data port.\r\n");
+    let _ = data.write_all(b"one\xff\x07");
+    drop(data);
+    server.assert_output(b"451 Invalid data received: unknown record control code 0x07.\r\n");
+
+    // the client sent bad data over a working connection
+    let mut stream = TcpStream::connect(metrics).unwrap();
+    write!(stream, "GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(
+        response.contains("\nftp_data_connection_failures_total 0\n"),
+        "{}",
+        response
+    );
+}
@@ -0,0 +1,73 @@
+use ftp::{
+    mock::{test_users, MockFtpServer},
+    CodePage, Config, User, Users,
+};
+
+fn only_b() -> Users {
+    let mut users = Users::new();
+    users.insert("b".to_owned(), User::new("b"));
+    users
+}
+
+#[test]
+fn reload_applies_to_new_sessions() {
+    let mut server = MockFtpServer::new();
+
+    server
+        .handle()
+        .reload(Config::new(only_b()).banner("Reloaded."));
+
+    // the open session keeps its original config
+    server.send_bytes(b"PWD\r\n");
+    server.assert_output(b"200 .\r\n");
+
+    let mut second = server.connect();
+    second.assert_output(b"220 Reloaded.\r\n");
+    second.send_bytes(b"USER a\r\n");
+    second.assert_output(b"530 User does not exist.\r\n");
+    second.login("b", "b");
+}
+
+#[test]
+fn refreshed_sessions_lose_removed_users() {
+    let mut a = MockFtpServer::new();
+    let mut b = a.connect();
+    b.assert_output(b"220 Server ready for new user.\r\n");
+    b.login("b", "b");
+
+    a.handle()
+        .reload(Config::new(only_b()).refresh_sessions(true));
+
+    a.send_bytes(b"PWD\r\n");
+    a.assert_output(b"421 Your account is no longer available.\r\n");
+    a.assert_closed();
+
+    b.send_bytes(b"PWD\r\n");
+    b.assert_output(b"200 .\r\n");
+}
+
+#[test]
+fn refreshed_sessions_follow_reloaded_settings() {
+    let mut server = MockFtpServer::new();
+
+    server.send_bytes(b"SITE IDLE 60\r\n");
+    server.assert_output(b"200 Idle timeout is now 60 seconds.\r\n");
+
+    server.handle().reload(
+        Config::new(test_users())
+            .ebcdic_code_page(CodePage::Cp1047)
+            .refresh_sessions(true),
+    );
+
+    server.send_bytes(b"SITE CODEPAGE\r\n");
+    server.assert_output(b"200 EBCDIC code page is 1047.\r\n");
+
+    // changed by the client, so kept
+    server.send_bytes(b"SITE IDLE\r\n");
+    let reply = server.read_line();
+    assert!(
+        reply.starts_with("200 Current idle timeout is 60 seconds;"),
+        "{:?}",
+        reply
+    );
+}
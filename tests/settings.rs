@@ -0,0 +1,70 @@
+use ftp::{mock::MockFtpServer, Config, Settings};
+
+const SETTINGS: &str = r#"
+listen = ["127.0.0.1:2121"]
+root = "."
+banner = "Welcome\nto the server."
+passive_ports = "62100-62109"
+
+[users.alice]
+password = "secret"
+quota_bytes = 1000
+allow = ["127.0.0.0/8"]
+
+[timeouts]
+idle = 60
+"#;
+
+fn config(settings: &str) -> Result<Config, String> {
+    let settings: Settings = settings.parse().map_err(|e| format!("{}", e))?;
+    let users = settings.users().map_err(|e| e.to_string())?;
+
+    settings
+        .apply(Config::new(users))
+        .map_err(|e| e.to_string())
+}
+
+#[test]
+fn configures_the_server() {
+    let mut server = MockFtpServer::start(config(SETTINGS).unwrap());
+
+    server.assert_output(b"220-Welcome\r\n220 to the server.\r\n");
+    server.login("alice", "secret");
+
+    let data_connection = server.pasv();
+    let port = data_connection.peer_addr().unwrap().port();
+    assert!((62100..=62109).contains(&port), "{}", port);
+}
+
+#[test]
+fn rejects_invalid_settings() {
+    for (settings, error) in &[
+        ("lisen = []", "unknown field `lisen`"),
+        (
+            "passive_ports = \"2000-1000\"",
+            "invalid passive port range",
+        ),
+        ("[access]\nallow = [\"10.0.0.0/33\"]", "invalid CIDR block"),
+        (
+            "[users.bob]\nhome = \"bob\"",
+            "user \"bob\" has no password",
+        ),
+        (
+            "[timeouts]\nidle = 10\nmin_idle = 20",
+            "min_idle <= idle <= max_idle",
+        ),
+        (
+            "[tls]\ncertificate = \"cert.pem\"\nkey = \"key.pem\"",
+            "unknown field `tls`",
+        ),
+    ] {
+        let result = config(settings);
+
+        assert!(
+            matches!(&result, Err(e) if e.contains(error)),
+            "{:?} gave {:?}",
+            settings,
+            result.map(|_| ())
+        );
+    }
+}
@@ -0,0 +1,124 @@
+use std::{collections::BTreeMap, fs};
+
+use ftp::{
+    mock::{scratch_dir, scratch_file, MockFtpServer},
+    Config, User,
+};
+
+/// Reads a multi-line reply, returning its lines without their CRLFs
+fn read_reply(server: &mut MockFtpServer) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    loop {
+        let line = server.read_line();
+        let done = line.as_bytes().get(3) == Some(&b' ');
+        lines.push(line.trim_end().to_owned());
+
+        if done {
+            return lines;
+        }
+    }
+}
+
+#[test]
+fn syst_and_help() {
+    let mut server = MockFtpServer::new();
+
+    server.send_bytes(b"SYST\r\n");
+    server.assert_output(b"215 UNIX Type: L8\r\n");
+
+    server.send_bytes(b"HELP\r\n");
+    let help = read_reply(&mut server);
+    assert_eq!(help[0], "214-The following commands are recognized.");
+    let verbs: Vec<&str> = help
+        .iter()
+        .flat_map(|line| line.split_whitespace())
+        .collect();
+    assert!(verbs.contains(&"RETR"));
+    assert!(!verbs.contains(&"STOU"));
+    assert_eq!(help.last().unwrap(), "214 Help OK.");
+
+    server.send_bytes(b"HELP retr\r\n");
+    server.assert_output(b"214 Syntax: RETR <SP> <pathname>\r\n");
+
+    server.send_bytes(b"HELP STOU\r\n");
+    server.assert_output(b"502 STOU is not implemented.\r\n");
+
+    server.send_bytes(b"HELP FOO\r\n");
+    server.assert_output(b"501 Unknown command FOO.\r\n");
+}
+
+#[test]
+fn every_listed_command_is_handled() {
+    let home = scratch_dir("help");
+    // keeps a bare RMD from removing the home directory
+    fs::write(home.join("file.txt"), b"hello").unwrap();
+
+    let mut users = BTreeMap::new();
+    users.insert("a".to_owned(), User::new("a").home(home.to_path_buf()));
+    let mut server = MockFtpServer::with_config(Config::new(users));
+
+    server.send_bytes(b"HELP\r\n");
+    let help = read_reply(&mut server);
+    let verbs: Vec<&str> = help[1..help.len() - 1]
+        .iter()
+        .flat_map(|line| line.split_whitespace())
+        .collect();
+
+    // each in a session of its own, as some end or reset the session
+    for verb in verbs {
+        let mut client = server.connect();
+        client.assert_output(b"220 Server ready for new user.\r\n");
+        client.login("a", "a");
+
+        client.send_bytes(format!("{}\r\n", verb).as_bytes());
+        let reply = read_reply(&mut client);
+        let last = reply.last().unwrap();
+        assert!(
+            last != "500 Command not recognized." && last != "502 Command not implemented.",
+            "{}: {:?}",
+            verb,
+            reply
+        );
+    }
+}
+
+#[test]
+fn stat_reports_session_and_lists_paths() {
+    let dir = scratch_file("stat");
+    fs::create_dir_all(dir.join("sub")).unwrap();
+
+    let file = dir.join("file.txt");
+    let mut server = MockFtpServer::new();
+    server.stor(file.to_str().unwrap(), b"hello");
+
+    server.send_bytes(b"STAT\r\n");
+    let status = read_reply(&mut server);
+    assert_eq!(status[0], "211-FTP server status:");
+    assert!(
+        status.contains(&" Logged in as a".to_owned()),
+        "{:?}",
+        status
+    );
+    assert!(
+        status.contains(&" TYPE: ASCII; STRUcture: file; transfer MODE: stream".to_owned()),
+        "{:?}",
+        status
+    );
+    assert!(status.contains(&" Data connection: none".to_owned()));
+    assert!(status.contains(&" Bytes transferred: 0 downloaded, 5 uploaded".to_owned()));
+    assert_eq!(status.last().unwrap(), "211 End of status.");
+
+    server.send_bytes(format!("STAT {}\r\n", dir.display()).as_bytes());
+    let listing = read_reply(&mut server);
+    assert_eq!(listing.len(), 4, "{:?}", listing);
+    assert!(listing[1].starts_with("-rw"), "{:?}", listing);
+    assert!(listing[1].contains(" 5 "), "{:?}", listing);
+    assert!(listing[1].ends_with(" file.txt"), "{:?}", listing);
+    assert!(listing[2].starts_with('d'), "{:?}", listing);
+    assert!(listing[2].ends_with(" sub"), "{:?}", listing);
+    assert_eq!(listing[3], "213 End of status.");
+
+    server.send_bytes(b"STAT /nonexistent\r\n");
+    assert!(server.read_line().starts_with("550 /nonexistent: "));
+}
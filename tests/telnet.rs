@@ -0,0 +1,36 @@
+use ftp::mock::MockFtpServer;
+
+const IAC: u8 = 255;
+
+#[test]
+fn refuses_option_negotiation() {
+    let mut server = MockFtpServer::new();
+
+    // DO ECHO, then WILL SUPPRESS-GO-AHEAD
+    server.send_bytes(b"\xff\xfd\x01\xff\xfb\x03NOOP\r\n");
+    server.assert_output(&[IAC, 252, 1]);
+    server.assert_output(&[IAC, 254, 3]);
+    server.assert_output(b"200 NOOP\r\n");
+
+    // acknowledgements of options we never enabled get no reply
+    server.send_bytes(b"\xff\xfc\x01\xff\xfe\x03NOOP\r\n");
+    server.assert_output(b"200 NOOP\r\n");
+}
+
+#[test]
+fn strips_commands_from_the_command_stream() {
+    let mut server = MockFtpServer::new();
+
+    // NOP and a subnegotiation in the middle of a verb
+    server.send_bytes(b"NO\xff\xf1O\xff\xfa\x18\x01\xff\xf0P\r\n");
+    server.assert_output(b"200 NOOP\r\n");
+
+    // Interrupt Process and Synch on their own are ignored
+    server.send_bytes(b"\xff\xf4\xff\xf2NOOP\r\n");
+    server.assert_output(b"200 NOOP\r\n");
+
+    // IAC IAC is a single 0xFF, which isn't valid UTF-8
+    server.send_bytes(b"STAT a\xff\xffb\r\n");
+    let reply = server.read_line();
+    assert!(reply.starts_with("550 a\u{fffd}b: "), "{:?}", reply);
+}
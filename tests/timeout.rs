@@ -0,0 +1,42 @@
+use std::time::Duration;
+
+use ftp::{
+    mock::{test_users, MockFtpServer},
+    Config, Timeouts,
+};
+
+fn short_timeouts() -> Config {
+    Config::new(test_users()).timeouts(Timeouts {
+        idle: Duration::from_secs(1),
+        min_idle: Duration::from_secs(1),
+        max_idle: Duration::from_secs(10),
+        login: Duration::from_secs(1),
+        ..Timeouts::default()
+    })
+}
+
+#[test]
+fn idle_timeout_sends_421() {
+    let mut server = MockFtpServer::with_config(short_timeouts());
+    server.assert_output(b"421 Idle timeout, closing control connection.\r\n");
+    server.assert_closed();
+}
+
+#[test]
+fn login_timeout_sends_421() {
+    let mut server = MockFtpServer::unauthenticated(short_timeouts());
+    server.send_bytes(b"USER a\r\n");
+    server.assert_output(b"331 Username Ok. Password needed.\r\n");
+    server.assert_output(b"421 Login timed out.\r\n");
+    server.assert_closed();
+}
+
+#[test]
+fn site_idle_within_limits() {
+    let mut server = MockFtpServer::with_config(short_timeouts());
+    server.send_bytes(b"SITE IDLE 5\r\n");
+    server.assert_output(b"200 Idle timeout is now 5 seconds.\r\n");
+    server.send_bytes(b"SITE IDLE 60\r\n");
+    server.assert_output(b"501 Idle timeout must be between 1 and 10 seconds.\r\n");
+    server.quit();
+}
@@ -0,0 +1,92 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Write,
+    thread,
+    time::{Duration, Instant},
+};
+
+use ftp::{
+    mock::{scratch_dir, test_users, MockFtpServer},
+    Config, RateLimits, User,
+};
+
+#[test]
+fn stor_then_retr_round_trips() {
+    let dir = scratch_dir("transfer-round-trip");
+    let path = dir.join("file.bin");
+    let path = path.to_str().unwrap();
+
+    let data: Vec<u8> = (0..=255).cycle().take(100_000).collect();
+
+    let mut server = MockFtpServer::new();
+    server.send_bytes(b"TYPE I\r\n");
+    server.assert_output(b"200 Type is now 8-bit binary.\r\n");
+    server.stor(path, &data);
+    assert_eq!(server.retr(path), data);
+    server.quit();
+}
+
+#[test]
+fn retr_missing_file_is_550() {
+    let mut server = MockFtpServer::new();
+    server.send_bytes(b"RETR /nonexistent/file\r\n");
+    server.assert_output(b"550 Error opening \"/nonexistent/file\": Not a file.\r\n");
+    server.quit();
+}
+
+#[test]
+fn downloads_are_throttled() {
+    let dir = scratch_dir("transfer-throttle");
+    let path = dir.join("file.bin");
+    fs::write(&path, vec![0; 3000]).unwrap();
+
+    let mut users = BTreeMap::new();
+    users.insert("a".to_owned(), User::new("a").download_rate(2000));
+
+    let mut server = MockFtpServer::with_config(Config::new(users).rate_limits(RateLimits {
+        global: Some(1000),
+        ..RateLimits::default()
+    }));
+
+    let start = Instant::now();
+    assert_eq!(server.retr(path.to_str().unwrap()).len(), 3000);
+
+    // the first second's worth is available immediately
+    assert!(start.elapsed().as_secs_f64() >= 1.8);
+
+    server.quit();
+}
+
+#[test]
+fn short_reads_are_charged_for_what_they_read() {
+    let dir = scratch_dir("transfer-short-reads");
+    let path = dir.join("file.bin");
+    let path = path.to_str().unwrap();
+
+    let mut server =
+        MockFtpServer::with_config(Config::new(test_users()).rate_limits(RateLimits {
+            per_session: Some(2000),
+            ..RateLimits::default()
+        }));
+
+    let mut data = server.pasv();
+    server.send_bytes(format!("STOR {}\r\n", path).as_bytes());
+    server.assert_output(b"150 Connecting to data port.\r\n");
+
+    // sent in pieces, so that each read fills little of the buffer
+    let start = Instant::now();
+    for _ in 0..30 {
+        data.write_all(&[0; 100]).unwrap();
+        thread::sleep(Duration::from_millis(5));
+    }
+    drop(data);
+    server.assert_output(b"226 Closing connection\r\n");
+
+    // the first 2000 bytes are available immediately, the rest at 2000/s
+    let elapsed = start.elapsed().as_secs_f64();
+    assert!((0.4..1.5).contains(&elapsed), "took {}s", elapsed);
+    assert_eq!(fs::read(path).unwrap().len(), 3000);
+
+    server.quit();
+}
@@ -0,0 +1,87 @@
+use std::{collections::BTreeMap, fs};
+
+use ftp::{
+    mock::{scratch_dir, scratch_file, test_users, MockFtpServer},
+    Config, User,
+};
+
+#[test]
+fn writes_a_record_per_transfer() {
+    let dir = scratch_dir("xferlog");
+
+    let log = dir.join("xferlog");
+    let file = dir.join("my file.txt");
+    let file = file.to_str().unwrap();
+
+    let mut server = MockFtpServer::with_config(Config::new(test_users()).xferlog(&log));
+
+    server.stor(file, b"hello");
+    server.send_bytes(b"TYPE I\r\n");
+    server.assert_output(b"200 Type is now 8-bit binary.\r\n");
+    server.retr(file);
+
+    server.send_bytes(b"QUIT\r\n");
+    server.assert_output(b"221 Goodbye!\r\n");
+
+    let log = fs::read_to_string(&log).unwrap();
+    let records: Vec<Vec<&str>> = log
+        .lines()
+        .map(|line| line.split_whitespace().collect())
+        .collect();
+
+    assert_eq!(records.len(), 2);
+
+    let expected_name = file.replace(' ', "_");
+
+    for (record, (kind, direction)) in records.iter().zip([("a", "i"), ("b", "o")]) {
+        assert_eq!(record.len(), 18, "{:?}", record);
+        assert_eq!(
+            &record[6..],
+            [
+                "127.0.0.1",
+                "5",
+                expected_name.as_str(),
+                kind,
+                "_",
+                direction,
+                "r",
+                "a",
+                "ftp",
+                "0",
+                "*",
+                "c"
+            ]
+        );
+    }
+}
+
+#[test]
+fn control_characters_cant_forge_records() {
+    let dir = scratch_file("xferlog-forged");
+    let home = dir.join("home\n127.0.0.1 forged\r");
+    fs::create_dir_all(&home).unwrap();
+
+    let log = dir.join("xferlog");
+
+    let mut users = BTreeMap::new();
+    users.insert("a".to_owned(), User::new("a").home(&home));
+    let mut server = MockFtpServer::with_config(Config::new(users).xferlog(&log));
+
+    server.stor("file.txt", b"hello");
+    server.quit();
+
+    let log = fs::read_to_string(&log).unwrap();
+    let records: Vec<Vec<&str>> = log
+        .lines()
+        .map(|line| line.split_whitespace().collect())
+        .collect();
+
+    assert_eq!(records.len(), 1, "{:?}", log);
+    assert_eq!(
+        records[0][8],
+        home.join("file.txt")
+            .to_str()
+            .unwrap()
+            .replace(['\n', '\r', ' '], "_")
+    );
+}